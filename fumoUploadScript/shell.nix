@@ -4,6 +4,6 @@ let
 
 in pkgs.mkShell {
   buildInputs = [
-    pkgs.wrangler
+    pkgs.curl
   ];
 }
\ No newline at end of file
@@ -1,12 +1,42 @@
 #!/usr/bin/env bash
-# Bash script that uploads all files on ./downloads to r2 instance using wrlanger
+# Bash script that uploads all files on ./downloads to the S3 compatible bucket the bot uses (R2 in production)
+# Only needed for bulk imports, the bot rehosts new submissions itself when they are pushed
+# Reads the same R2_* variables as the bot, from the environment or ../.env
+set -euo pipefail
 
-files=$(ls ./downloads)
-i=0
-for file in $files
-do 
+if [ -f ../.env ]; then
+    set -a
+    source ../.env
+    set +a
+fi
+: "${R2_ENDPOINT:?R2_ENDPOINT is not set}"
+: "${R2_ACCESS_KEY_ID:?R2_ACCESS_KEY_ID is not set}"
+: "${R2_SECRET_ACCESS_KEY:?R2_SECRET_ACCESS_KEY is not set}"
+bucket=${R2_BUCKET:-nosesisaid-cdn}
+region=${R2_REGION:-auto}
+
+# Path style addressing and SigV4 signing, like the bot
+s3() {
+    curl --fail --silent --show-error \
+        --aws-sigv4 "aws:amz:$region:s3" \
+        --user "$R2_ACCESS_KEY_ID:$R2_SECRET_ACCESS_KEY" \
+        "$@"
+}
+
+uploaded=0
+skipped=0
+for path in ./downloads/*
+do
+    [ -f "$path" ] || continue
+    file=$(basename "$path")
+    url="${R2_ENDPOINT%/}/$bucket/$file"
+    if s3 --head --output /dev/null "$url" 2>/dev/null; then
+        echo "Skipping $file, already uploaded"
+        skipped=$((skipped+1))
+        continue
+    fi
     echo "Uploading $file"
-    wrangler r2 object put nosesisaid-cdn/$file --file=downloads/$file
-    i=$((i+1))
+    s3 --upload-file "$path" --output /dev/null "$url"
+    uploaded=$((uploaded+1))
 done
-echo "Uploaded $i files"
\ No newline at end of file
+echo "Uploaded $uploaded files, $skipped were already uploaded"
@@ -0,0 +1,272 @@
+use crate::{curators::ensure_curator, settings::BlacklistScope, Context, Data, Error};
+use mongodb::{
+    bson::{doc, Document},
+    Collection,
+};
+use poise::{serenity_prelude as serenity, CreateReply};
+use serde::{Deserialize, Serialize};
+use serenity::{futures::TryStreamExt, GuildId, UserId};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlacklistDoc {
+    /// Discord user id
+    _id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// Curator that blacklisted the user
+    issued_by: String,
+    issued_at: i64,
+    /// Guild the user was blacklisted from, see `BlacklistScope`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issued_in: Option<String>,
+    /// Unix timestamp after which the user can use the bot again, `None` for never
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+}
+
+impl BlacklistDoc {
+    fn is_active(&self, now: i64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+/// Loads the active blacklist entries, dropping the expired ones from the database
+pub async fn load(
+    blacklist: &Collection<BlacklistDoc>,
+) -> Result<HashMap<UserId, BlacklistDoc>, Error> {
+    let now = chrono::Utc::now().timestamp();
+    blacklist
+        .delete_many(doc! { "expires_at": { "$lte": now } })
+        .await?;
+    let mut entries = HashMap::new();
+    let mut cursor = blacklist.find(doc! {}).await?;
+    while let Some(entry) = cursor.try_next().await? {
+        entries.insert(entry._id.parse()?, entry);
+    }
+    Ok(entries)
+}
+
+/// Adds users from the old `USERS_IN_BLACKLIST` setting to the database. Each id is only
+/// imported once, the imported ones are recorded in `migrations`, so users removed with
+/// `/blacklist remove` aren't blacklisted again at the next start. Returns how many were imported
+pub async fn import_legacy(
+    blacklist: &Collection<BlacklistDoc>,
+    migrations: &Collection<Document>,
+    users: &[String],
+) -> Result<u64, Error> {
+    let marker = doc! { "_id": "users_in_blacklist" };
+    let imported: Vec<String> = migrations
+        .find_one(marker.clone())
+        .await?
+        .and_then(|marker| marker.get_array("users").ok().cloned())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|user| user.as_str().map(|user| user.to_owned()))
+        .collect();
+    let mut count = 0;
+    for user in users.iter().filter(|user| !imported.contains(user)) {
+        let user: UserId = user.parse()?;
+        blacklist
+            .update_one(
+                doc! { "_id": user.to_string() },
+                doc! { "$setOnInsert": {
+                    "reason": "Imported from USERS_IN_BLACKLIST",
+                    "issued_by": "environment",
+                    "issued_at": chrono::Utc::now().timestamp(),
+                } },
+            )
+            .upsert(true)
+            .await?;
+        migrations
+            .update_one(
+                marker.clone(),
+                doc! { "$addToSet": { "users": user.to_string() } },
+            )
+            .upsert(true)
+            .await?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+impl Data {
+    /// Whether an entry is enforced in a guild, every entry is enforced outside of guilds
+    fn blacklist_applies(&self, entry: &BlacklistDoc, guild: Option<GuildId>) -> bool {
+        let scope = self
+            .guild_settings(guild)
+            .map(|settings| settings.blacklist_scope)
+            .unwrap_or_default();
+        match (scope, guild) {
+            (BlacklistScope::Server, Some(guild)) => {
+                entry.issued_in.as_deref() == Some(&guild.to_string())
+            }
+            _ => true,
+        }
+    }
+
+    pub fn is_blacklisted(&self, user: UserId, guild: Option<GuildId>) -> bool {
+        self.blacklist
+            .read()
+            .unwrap()
+            .get(&user)
+            .is_some_and(|entry| {
+                entry.is_active(chrono::Utc::now().timestamp())
+                    && self.blacklist_applies(entry, guild)
+            })
+    }
+}
+
+/// Parses durations like `30m`, `12h`, `7d` or `2w` into seconds
+fn parse_duration(duration: &str) -> Option<i64> {
+    let duration = duration.trim();
+    let unit = duration.chars().last()?;
+    let amount: i64 = duration[..duration.len() - unit.len_utf8()].parse().ok()?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    amount.checked_mul(seconds).filter(|s| *s > 0)
+}
+
+/// Manage users that can't use the bot
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("add", "remove", "list"),
+    subcommand_required
+)]
+pub async fn blacklist(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Stop someone from using the bot and submitting fumos
+#[poise::command(prefix_command, slash_command)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "The user to blacklist"] user: serenity::User,
+    #[description = "Why they are blacklisted"] reason: Option<String>,
+    #[description = "How long, like 12h, 7d or 2w. Forever if empty"] duration: Option<String>,
+) -> Result<(), Error> {
+    ensure_curator(ctx).await?;
+    let data = ctx.data();
+    if ctx.framework().options().owners.contains(&user.id) {
+        return Err(Error::Permission(
+            "Bot owners can't be blacklisted".to_owned(),
+        ));
+    }
+    let roles = match ctx.guild_id() {
+        Some(guild) => guild
+            .member(ctx, user.id)
+            .await
+            .map(|member| member.roles)
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    if data.is_curator(user.id, ctx.guild_id(), &roles) {
+        return Err(Error::Permission(
+            "Curators can't be blacklisted, remove them from the curators first".to_owned(),
+        ));
+    }
+    let now = chrono::Utc::now().timestamp();
+    let expires_at = match duration.as_deref().map(parse_duration) {
+        None => None,
+        Some(Some(seconds)) => Some(now + seconds),
+        Some(None) => {
+            ctx.reply("Invalid duration, use something like `30m`, `12h`, `7d` or `2w`")
+                .await?;
+            return Ok(());
+        }
+    };
+    let entry = BlacklistDoc {
+        _id: user.id.to_string(),
+        reason,
+        issued_by: ctx.author().id.to_string(),
+        issued_at: now,
+        issued_in: ctx.guild_id().map(|g| g.to_string()),
+        expires_at,
+    };
+
+    data.blacklist_collection
+        .replace_one(doc! { "_id": &entry._id }, &entry)
+        .upsert(true)
+        .await?;
+    data.blacklist.write().unwrap().insert(user.id, entry);
+    ctx.reply(format!(
+        "<@{}> is blacklisted {}",
+        user.id,
+        expires_at
+            .map(|e| format!("until <t:{}:f>", e))
+            .unwrap_or_else(|| "forever".to_owned())
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Let someone use the bot again
+#[poise::command(prefix_command, slash_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The user to remove from the blacklist"] user: serenity::User,
+) -> Result<(), Error> {
+    ensure_curator(ctx).await?;
+    let data = ctx.data();
+    data.blacklist_collection
+        .delete_one(doc! { "_id": user.id.to_string() })
+        .await?;
+    let removed = data.blacklist.write().unwrap().remove(&user.id);
+    if removed.is_some() {
+        ctx.reply(format!("<@{}> is no longer blacklisted", user.id))
+            .await?;
+    } else {
+        ctx.reply(format!("<@{}> was not blacklisted", user.id))
+            .await?;
+    }
+    Ok(())
+}
+
+/// List blacklisted users
+#[poise::command(prefix_command, slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    ensure_curator(ctx).await?;
+    let data = ctx.data();
+    let now = chrono::Utc::now().timestamp();
+    let lines: Vec<String> = data
+        .blacklist
+        .read()
+        .unwrap()
+        .values()
+        .filter(|entry| entry.is_active(now) && data.blacklist_applies(entry, ctx.guild_id()))
+        .map(|entry| {
+            format!(
+                "- <@{}> by <@{}> {}: {}",
+                entry._id,
+                entry.issued_by,
+                entry
+                    .expires_at
+                    .map(|e| format!("until <t:{}:R>", e))
+                    .unwrap_or_else(|| "forever".to_owned()),
+                entry.reason.as_deref().unwrap_or("No reason given")
+            )
+        })
+        .collect();
+    let description = if lines.is_empty() {
+        "Nobody is blacklisted".to_owned()
+    } else {
+        lines.join("\n")
+    };
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                serenity::CreateEmbed::new()
+                    .title("Blacklist")
+                    .description(description),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,648 @@
+//! Registry of the characters fumos feature, so `featured` always uses the same name
+//! for a character however the submitter wrote it
+use crate::{curators::ensure_curator, submissions::SubmissionInfo, Context, Data, Error};
+use mongodb::{bson::doc, Collection};
+use poise::{serenity_prelude as serenity, CreateReply};
+use serde::{Deserialize, Serialize};
+use serenity::futures::TryStreamExt;
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CharacterDoc {
+    /// Canonical id, like `reimu_hakurei`
+    pub _id: String,
+    /// Canonical name, the one stored in `featured`
+    pub name: String,
+    /// Other names in English, Japanese or romaji
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Fumo release series the character got a plush in
+    #[serde(default)]
+    pub series: Vec<String>,
+}
+
+/// Registry the database starts with, curators add the rest with `/characters`
+const DEFAULT_CHARACTERS: &[(&str, &str, &[&str])] = &[
+    (
+        "reimu_hakurei",
+        "Reimu Hakurei",
+        &["Reimu", "Hakurei Reimu", "博麗霊夢", "霊夢"],
+    ),
+    (
+        "marisa_kirisame",
+        "Marisa Kirisame",
+        &["Marisa", "Kirisame Marisa", "霧雨魔理沙", "魔理沙"],
+    ),
+    (
+        "sakuya_izayoi",
+        "Sakuya Izayoi",
+        &["Sakuya", "Izayoi Sakuya", "十六夜咲夜", "咲夜"],
+    ),
+    (
+        "remilia_scarlet",
+        "Remilia Scarlet",
+        &["Remilia", "Remi", "レミリア・スカーレット", "レミリア"],
+    ),
+    (
+        "flandre_scarlet",
+        "Flandre Scarlet",
+        &["Flandre", "Flan", "フランドール・スカーレット", "フラン"],
+    ),
+    (
+        "hong_meiling",
+        "Hong Meiling",
+        &["Meiling", "Meirin", "紅美鈴", "美鈴"],
+    ),
+    (
+        "patchouli_knowledge",
+        "Patchouli Knowledge",
+        &["Patchouli", "パチュリー・ノーレッジ", "パチュリー"],
+    ),
+    ("cirno", "Cirno", &["チルノ", "⑨"]),
+    ("rumia", "Rumia", &["ルーミア"]),
+    (
+        "alice_margatroid",
+        "Alice Margatroid",
+        &["Alice", "アリス・マーガトロイド", "アリス"],
+    ),
+    (
+        "youmu_konpaku",
+        "Youmu Konpaku",
+        &["Youmu", "Konpaku Youmu", "魂魄妖夢", "妖夢"],
+    ),
+    (
+        "yuyuko_saigyouji",
+        "Yuyuko Saigyouji",
+        &["Yuyuko", "Saigyouji Yuyuko", "西行寺幽々子", "幽々子"],
+    ),
+    ("chen", "Chen", &["橙"]),
+    (
+        "ran_yakumo",
+        "Ran Yakumo",
+        &["Ran", "Yakumo Ran", "八雲藍", "藍"],
+    ),
+    (
+        "yukari_yakumo",
+        "Yukari Yakumo",
+        &["Yukari", "Yakumo Yukari", "八雲紫", "紫"],
+    ),
+    (
+        "suika_ibuki",
+        "Suika Ibuki",
+        &["Suika", "Ibuki Suika", "伊吹萃香", "萃香"],
+    ),
+    (
+        "reisen_udongein_inaba",
+        "Reisen Udongein Inaba",
+        &["Reisen", "Udonge", "鈴仙・優曇華院・イナバ", "鈴仙"],
+    ),
+    (
+        "tewi_inaba",
+        "Tewi Inaba",
+        &["Tewi", "Inaba Tewi", "因幡てゐ", "てゐ"],
+    ),
+    (
+        "eirin_yagokoro",
+        "Eirin Yagokoro",
+        &["Eirin", "Yagokoro Eirin", "八意永琳", "永琳"],
+    ),
+    (
+        "kaguya_houraisan",
+        "Kaguya Houraisan",
+        &["Kaguya", "Houraisan Kaguya", "蓬莱山輝夜", "輝夜"],
+    ),
+    (
+        "fujiwara_no_mokou",
+        "Fujiwara no Mokou",
+        &["Mokou", "藤原妹紅", "妹紅"],
+    ),
+    (
+        "aya_shameimaru",
+        "Aya Shameimaru",
+        &["Aya", "Shameimaru Aya", "射命丸文", "文"],
+    ),
+    (
+        "momiji_inubashiri",
+        "Momiji Inubashiri",
+        &["Momiji", "Inubashiri Momiji", "犬走椛", "椛"],
+    ),
+    (
+        "nitori_kawashiro",
+        "Nitori Kawashiro",
+        &["Nitori", "Kawashiro Nitori", "河城にとり", "にとり"],
+    ),
+    (
+        "sanae_kochiya",
+        "Sanae Kochiya",
+        &["Sanae", "Kochiya Sanae", "東風谷早苗", "早苗"],
+    ),
+    (
+        "tenshi_hinanawi",
+        "Tenshi Hinanawi",
+        &["Tenshi", "Hinanawi Tenshi", "比那名居天子", "天子"],
+    ),
+    (
+        "satori_komeiji",
+        "Satori Komeiji",
+        &["Satori", "Komeiji Satori", "古明地さとり", "さとり"],
+    ),
+    (
+        "koishi_komeiji",
+        "Koishi Komeiji",
+        &["Koishi", "Komeiji Koishi", "古明地こいし", "こいし"],
+    ),
+    (
+        "rin_kaenbyou",
+        "Rin Kaenbyou",
+        &["Orin", "Kaenbyou Rin", "火焔猫燐", "お燐"],
+    ),
+    (
+        "utsuho_reiuji",
+        "Utsuho Reiuji",
+        &["Okuu", "Utsuho", "Reiuji Utsuho", "霊烏路空", "お空"],
+    ),
+];
+
+/// Separators between the characters of an image with several of them
+const SEPARATORS: &[char] = &[',', '&', '+', '/', '、', ';'];
+
+/// In memory copy of the characters stored in mongo, updated by the characters commands
+#[derive(Default)]
+pub struct CharacterRegistry {
+    characters: HashMap<String, CharacterDoc>,
+    /// Every name and alias, see `name_key`, to the id of its character
+    names: HashMap<String, String>,
+}
+
+/// Which characters a `featured` value names
+pub struct Featured {
+    /// Canonical ids of the known characters
+    pub ids: Vec<String>,
+    /// Canonical names, followed by the unknown names as written
+    pub names: Vec<String>,
+    pub unknown: Vec<String>,
+}
+
+/// Names are compared ignoring case, spaces and punctuation, so `Reimu hakurei`,
+/// `reimu-hakurei` and `ReimuHakurei` are the same
+fn name_key(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Id of a new character, from its canonical name
+fn character_id(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+impl CharacterRegistry {
+    pub async fn load(characters: &Collection<CharacterDoc>) -> Result<Self, Error> {
+        let mut registry = Self::default();
+        let mut characters = characters.find(doc! {}).await?;
+        while let Some(character) = characters.try_next().await? {
+            registry.insert(character);
+        }
+        Ok(registry)
+    }
+
+    fn insert(&mut self, character: CharacterDoc) {
+        for name in std::iter::once(&character.name).chain(&character.aliases) {
+            let key = name_key(name);
+            if !key.is_empty() {
+                self.names
+                    .entry(key)
+                    .or_insert_with(|| character._id.clone());
+            }
+        }
+        self.characters.insert(character._id.clone(), character);
+    }
+
+    /// Character with that id, name or alias
+    pub fn find(&self, name: &str) -> Option<&CharacterDoc> {
+        self.characters
+            .get(name)
+            .or_else(|| self.characters.get(self.names.get(&name_key(name))?))
+    }
+
+    /// Splits a `featured` value into the characters it names, duplicates are dropped
+    pub fn resolve(&self, featured: &str) -> Featured {
+        let mut resolved = Featured {
+            ids: Vec::new(),
+            names: Vec::new(),
+            unknown: Vec::new(),
+        };
+        for name in featured
+            .split(SEPARATORS)
+            .flat_map(|name| name.split(" and "))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            match self.find(name) {
+                Some(character) if resolved.ids.contains(&character._id) => {}
+                Some(character) => {
+                    resolved.ids.push(character._id.clone());
+                    resolved.names.push(character.name.clone());
+                }
+                None if resolved
+                    .unknown
+                    .iter()
+                    .any(|u| name_key(u) == name_key(name)) => {}
+                None => resolved.unknown.push(name.to_owned()),
+            }
+        }
+        resolved.names.extend(resolved.unknown.iter().cloned());
+        resolved
+    }
+
+    /// Canonical names of the characters with a name or alias containing `partial`,
+    /// the ones starting with it first
+    pub fn suggest(&self, partial: &str, limit: usize) -> Vec<String> {
+        let partial = name_key(partial);
+        let mut matches: Vec<_> = self
+            .characters
+            .values()
+            .filter_map(|character| {
+                let keys: Vec<_> = std::iter::once(&character.name)
+                    .chain(&character.aliases)
+                    .map(|name| name_key(name))
+                    .collect();
+                let starts = keys.iter().any(|key| key.starts_with(&partial));
+                (starts || keys.iter().any(|key| key.contains(&partial)))
+                    .then_some((!starts, character.name.as_str()))
+            })
+            .collect();
+        matches.sort();
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, name)| name.to_owned())
+            .collect()
+    }
+
+    /// Names of every character, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.characters.values().map(|c| c.name.clone()).collect();
+        names.sort();
+        names
+    }
+}
+
+/// Fills an empty registry with `DEFAULT_CHARACTERS`
+pub async fn seed(characters: &Collection<CharacterDoc>) -> Result<u64, Error> {
+    if characters.estimated_document_count().await? > 0 {
+        return Ok(0);
+    }
+    Ok(characters
+        .insert_many(default_characters())
+        .await?
+        .inserted_ids
+        .len() as u64)
+}
+
+fn default_characters() -> impl Iterator<Item = CharacterDoc> {
+    DEFAULT_CHARACTERS
+        .iter()
+        .map(|(id, name, aliases)| CharacterDoc {
+            _id: (*id).to_owned(),
+            name: (*name).to_owned(),
+            aliases: aliases.iter().map(|alias| (*alias).to_owned()).collect(),
+            series: Vec::new(),
+        })
+}
+
+impl Data {
+    /// Replaces `featured` with the canonical names of the characters it names and
+    /// fills `characters` with their ids. Returns the names that aren't in the registry,
+    /// which are kept as written
+    pub fn resolve_featured(&self, info: &mut SubmissionInfo) -> Vec<String> {
+        let Some(featured) = &info.featured else {
+            info.characters = Vec::new();
+            return Vec::new();
+        };
+        let resolved = self.characters.read().unwrap().resolve(featured);
+        info.featured = Some(resolved.names.join(", ")).filter(|names| !names.is_empty());
+        info.characters = resolved.ids;
+        resolved.unknown
+    }
+
+    /// Canonical name of a character, or the name as written when it isn't registered
+    pub fn canonical_character(&self, name: &str) -> String {
+        self.characters
+            .read()
+            .unwrap()
+            .find(name)
+            .map_or_else(|| name.trim().to_owned(), |c| c.name.clone())
+    }
+}
+
+/// Suggests registered characters for a command argument
+pub async fn autocomplete_character(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    // Discord shows at most 25 choices
+    ctx.data().characters.read().unwrap().suggest(partial, 25)
+}
+
+/// Comma separated list of a command argument
+fn list_argument(list: Option<String>) -> Vec<String> {
+    list.unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_owned())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Manage the characters fumos can feature
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("add", "alias", "series", "list"),
+    subcommand_required
+)]
+pub async fn characters(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Register a character
+#[poise::command(prefix_command, slash_command)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "Canonical name, like Reimu Hakurei"] name: String,
+    #[description = "Other names in English, Japanese or romaji, comma separated"] aliases: Option<
+        String,
+    >,
+    #[description = "Fumo release series, comma separated"] series: Option<String>,
+) -> Result<(), Error> {
+    ensure_curator(ctx).await?;
+    let data = ctx.data();
+    let name = name.trim().to_owned();
+    let id = character_id(&name);
+    if id.is_empty() {
+        return Err(Error::Validation(format!("`{}` is not a name", name)));
+    }
+    let aliases = list_argument(aliases);
+    {
+        let registry = data.characters.read().unwrap();
+        for taken in std::iter::once(&name).chain(&aliases) {
+            if let Some(character) = registry.find(taken) {
+                return Err(Error::Validation(format!(
+                    "`{}` is already used by {}",
+                    taken, character.name
+                )));
+            }
+        }
+    }
+    let character = CharacterDoc {
+        _id: id,
+        name,
+        aliases,
+        series: list_argument(series),
+    };
+    data.characters_collection.insert_one(&character).await?;
+    let reply = format!("{} (`{}`) is now registered", character.name, character._id);
+    data.characters.write().unwrap().insert(character);
+    ctx.reply(reply).await?;
+    Ok(())
+}
+
+/// Add another name to a character
+#[poise::command(prefix_command, slash_command)]
+pub async fn alias(
+    ctx: Context<'_>,
+    #[description = "Character, by any of their names"]
+    #[autocomplete = "autocomplete_character"]
+    character: String,
+    #[description = "The new name, in English, Japanese or romaji"] alias: String,
+) -> Result<(), Error> {
+    ensure_curator(ctx).await?;
+    let data = ctx.data();
+    let alias = alias.trim().to_owned();
+    if name_key(&alias).is_empty() {
+        return Err(Error::Validation(format!("`{}` is not a name", alias)));
+    }
+    let id = {
+        let registry = data.characters.read().unwrap();
+        if let Some(taken) = registry.find(&alias) {
+            return Err(Error::Validation(format!(
+                "`{}` is already used by {}",
+                alias, taken.name
+            )));
+        }
+        find_id(&registry, &character)?
+    };
+    let name = update_character(ctx, &id, doc! { "$addToSet": { "aliases": &alias } }).await?;
+    ctx.reply(format!("`{}` now also means {}", alias, name))
+        .await?;
+    Ok(())
+}
+
+/// Record a fumo release series a character got a plush in
+#[poise::command(prefix_command, slash_command)]
+pub async fn series(
+    ctx: Context<'_>,
+    #[description = "Character, by any of their names"]
+    #[autocomplete = "autocomplete_character"]
+    character: String,
+    #[description = "The release series"] series: String,
+) -> Result<(), Error> {
+    ensure_curator(ctx).await?;
+    let series = series.trim().to_owned();
+    if series.is_empty() {
+        return Err(Error::Validation("The series can't be empty".to_owned()));
+    }
+    let id = find_id(&ctx.data().characters.read().unwrap(), &character)?;
+    let name = update_character(ctx, &id, doc! { "$addToSet": { "series": &series } }).await?;
+    ctx.reply(format!("Added {} to the series of {}", series, name))
+        .await?;
+    Ok(())
+}
+
+/// List the registered characters
+#[poise::command(prefix_command, slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let description = {
+        let registry = ctx.data().characters.read().unwrap();
+        let lines: Vec<_> = registry
+            .names()
+            .iter()
+            .filter_map(|name| registry.find(name))
+            .map(|character| {
+                let mut line = format!("- **{}**", character.name);
+                if !character.aliases.is_empty() {
+                    line += &format!(" ({})", character.aliases.join(", "));
+                }
+                if !character.series.is_empty() {
+                    line += &format!(" · {}", character.series.join(", "));
+                }
+                line
+            })
+            .collect();
+        if lines.is_empty() {
+            "No characters yet".to_owned()
+        } else {
+            lines.join("\n")
+        }
+    };
+    // Embed descriptions are limited to 4096 characters
+    let description = if description.chars().count() > 4096 {
+        description.chars().take(4093).collect::<String>() + "..."
+    } else {
+        description
+    };
+    ctx.send(
+        CreateReply::default().embed(
+            serenity::CreateEmbed::new()
+                .title("Characters")
+                .description(description),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+fn find_id(registry: &CharacterRegistry, character: &str) -> Result<String, Error> {
+    registry
+        .find(character)
+        .map(|character| character._id.clone())
+        .ok_or_else(|| Error::NotFound(format!("No character named {}", character.trim())))
+}
+
+/// Applies the update to a character and refreshes the registry with the result,
+/// returning the name of the character
+async fn update_character(
+    ctx: Context<'_>,
+    id: &str,
+    update: mongodb::bson::Document,
+) -> Result<String, Error> {
+    let data = ctx.data();
+    let character = data
+        .characters_collection
+        .find_one_and_update(doc! { "_id": id }, update)
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("No character with id {}", id)))?;
+    let name = character.name.clone();
+    data.characters.write().unwrap().insert(character);
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> CharacterRegistry {
+        let mut registry = CharacterRegistry::default();
+        default_characters().for_each(|character| registry.insert(character));
+        registry
+    }
+
+    #[test]
+    fn names_ignore_case_spaces_and_punctuation() {
+        let key = name_key("Reimu Hakurei");
+        for name in [
+            "reimu hakurei",
+            "reimu-hakurei",
+            "ReimuHakurei",
+            " REIMU.hakurei! ",
+        ] {
+            assert_eq!(name_key(name), key, "{}", name);
+        }
+        assert_eq!(name_key("博麗 霊夢"), "博麗霊夢");
+        assert_eq!(character_id("Fujiwara no Mokou"), "fujiwara_no_mokou");
+    }
+
+    #[test]
+    fn finds_characters_by_id_name_or_alias() {
+        let registry = registry();
+        for name in [
+            "reimu_hakurei",
+            "Reimu Hakurei",
+            "hakurei reimu",
+            "霊夢",
+            "reimu",
+        ] {
+            assert_eq!(
+                registry.find(name).map(|c| c._id.as_str()),
+                Some("reimu_hakurei"),
+                "{}",
+                name
+            );
+        }
+        assert!(registry.find("Ruukoto").is_none());
+    }
+
+    #[test]
+    fn default_names_are_unambiguous() {
+        let mut owners = HashMap::new();
+        for character in default_characters() {
+            for name in std::iter::once(&character.name).chain(&character.aliases) {
+                if let Some(other) = owners.insert(name_key(name), character._id.clone()) {
+                    assert_eq!(other, character._id, "{} is used twice", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resolves_every_separator() {
+        let resolved =
+            registry().resolve("reimu, Marisa & sakuya/remilia + flandre; meiling、alice");
+        assert_eq!(
+            resolved.ids,
+            [
+                "reimu_hakurei",
+                "marisa_kirisame",
+                "sakuya_izayoi",
+                "remilia_scarlet",
+                "flandre_scarlet",
+                "hong_meiling",
+                "alice_margatroid",
+            ]
+        );
+        assert!(resolved.unknown.is_empty());
+    }
+
+    #[test]
+    fn splits_on_and_but_not_inside_names() {
+        let resolved = registry().resolve("Reimu and Fujiwara no Mokou");
+        assert_eq!(resolved.ids, ["reimu_hakurei", "fujiwara_no_mokou"]);
+        assert_eq!(resolved.names, ["Reimu Hakurei", "Fujiwara no Mokou"]);
+        // Only the word, not `and` inside a name
+        let resolved = registry().resolve("Sandy");
+        assert_eq!(resolved.unknown, ["Sandy"]);
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let resolved = registry().resolve("Reimu, reimu hakurei, 霊夢, Ruukoto, ruukoto");
+        assert_eq!(resolved.ids, ["reimu_hakurei"]);
+        assert_eq!(resolved.unknown, ["Ruukoto"]);
+        assert_eq!(resolved.names, ["Reimu Hakurei", "Ruukoto"]);
+    }
+
+    #[test]
+    fn keeps_unknown_names_as_written_after_the_known_ones() {
+        let resolved = registry().resolve("Ruukoto, marisa, ,  ");
+        assert_eq!(resolved.ids, ["marisa_kirisame"]);
+        assert_eq!(resolved.names, ["Marisa Kirisame", "Ruukoto"]);
+        assert_eq!(resolved.unknown, ["Ruukoto"]);
+    }
+
+    #[test]
+    fn suggests_prefixes_first() {
+        let registry = registry();
+        // Alice Margatroid only contains it
+        assert_eq!(
+            registry.suggest("mar", 2),
+            ["Marisa Kirisame", "Alice Margatroid"]
+        );
+        assert_eq!(registry.suggest("mar", 1), ["Marisa Kirisame"]);
+        // Aliases count, the canonical name is suggested
+        assert_eq!(registry.suggest("霊夢", 25), ["Reimu Hakurei"]);
+        assert!(registry.suggest("zzz", 25).is_empty());
+    }
+}
@@ -1,10 +1,15 @@
-use crate::{Context, Error, FumoDoc};
+use crate::{
+    characters::autocomplete_character,
+    curators::ensure_curator,
+    duplicates, media,
+    promotion::{promote_submission, PromotionOutcome},
+    storage,
+    submissions::SubmissionStatus,
+    Context, Data, Error,
+};
 use ::serenity::all::{CreateEmbedAuthor, CreateEmbedFooter};
-use mongodb::bson::doc;
+use mongodb::bson::{doc, Bson, Document};
 use poise::{serenity_prelude as serenity, CreateReply};
-use serde;
-use serde::{Deserialize, Serialize};
-use serde_json;
 use serenity::futures::TryStreamExt;
 
 pub struct Fumo {
@@ -16,14 +21,61 @@ pub struct Fumo {
     pub featured: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct APIFumo {
-    pub _id: String,
-    pub caption: Option<String>,
-    pub url: String,
-    pub source: Option<String>,
-    pub credit: Option<String>,
-    pub featured: Option<String>,
+impl Fumo {
+    /// Fumo of the fumos collection, read field by field since the documents added by the
+    /// upload script don't have the same shape as `FumoDoc`. `None` when it has no media
+    pub fn from_document(fumo: &Document) -> Option<Self> {
+        let text = |field: &str| fumo.get_str(field).ok().map(str::to_owned);
+        Some(Self {
+            _id: text("_id")?,
+            caption: text("caption"),
+            image: fumo_media_url(fumo)?.to_owned(),
+            source: text("source"),
+            credit: text("credit"),
+            featured: text("featured"),
+        })
+    }
+}
+
+/// Media of a document of the fumos collection. Fumos added by the upload script use
+/// `url` instead of `image_url`, projections need both fields
+pub fn fumo_media_url(fumo: &Document) -> Option<&str> {
+    fumo.get_str("image_url")
+        .or_else(|_| fumo.get_str("url"))
+        .ok()
+}
+
+/// Length the snowflake of the ids is padded to, the longest a `u64` can have
+const ID_DIGITS: usize = 20;
+
+/// Key sorting fumo ids in submission order. Ids are snowflakes stored as strings, the
+/// ones imported from the archive are shorter than the new ones, and the attachments after
+/// the first of a message have a `-<index>` suffix, see `submission_id`
+pub fn id_order_key(id: &str) -> String {
+    let digits = id.find('-').unwrap_or(id.len());
+    format!("{}{}", "0".repeat(ID_DIGITS.saturating_sub(digits)), id)
+}
+
+/// `$addFields` stage computing `id_order_key` of `_id` as `order`
+pub fn id_order_stage() -> Document {
+    doc! { "$addFields": { "order": { "$let": {
+        "vars": { "dash": { "$indexOfCP": ["$_id", "-"] } },
+        "in": { "$let": {
+            "vars": { "digits": { "$cond": [
+                { "$gte": ["$$dash", 0] },
+                "$$dash",
+                { "$strLenCP": "$_id" },
+            ] } },
+            "in": { "$concat": [
+                { "$substrCP": [
+                    "0".repeat(ID_DIGITS),
+                    0,
+                    { "$max": [0, { "$subtract": [ID_DIGITS as i32, "$$digits"] }] },
+                ] },
+                "$_id",
+            ] },
+        } },
+    } } } }
 }
 
 /// Show this help menu
@@ -51,33 +103,89 @@ pub async fn hello(
     ctx: Context<'_>,
     #[description = "The name of the person you want to greet"] who: Option<serenity::User>,
 ) -> Result<(), Error> {
-    if who.is_some() {
-        let response = format!(
-            "Hello {}! {} is greeting you",
-            who.unwrap().name,
-            ctx.author().name
-        );
+    if let Some(who) = who {
+        let response = format!("Hello {}! {} is greeting you", who.name, ctx.author().name);
         ctx.say(response).await?;
         return Ok(());
     }
-    let response = format!("Hello chat how you doin!");
-    ctx.say(response).await?;
+    ctx.say("Hello chat how you doin!").await?;
     Ok(())
 }
-#[poise::command(prefix_command, slash_command)]
-pub async fn fumo(
+/// Suggests fumo ids starting with what was typed, or whose caption contains it
+async fn autocomplete_fumo_id(
     ctx: Context<'_>,
-    #[description = "The id of the fumo you want to search for"] fumo: String,
-) -> Result<(), Error> {
-    let data = ctx.data();
-    let client = data.web_client.clone();
+    partial: &str,
+) -> Vec<serenity::AutocompleteChoice> {
+    match fumo_id_choices(ctx, partial.trim()).await {
+        Ok(choices) => choices,
+        Err(e) => {
+            println!("Failed to autocomplete fumo ids: {:?}", e);
+            Vec::new()
+        }
+    }
+}
 
-    let res = client.get(format!("{}/fumo/{}", data.fumo_api_endpoint, fumo));
-    let res = res.send().await.expect("Failed to retrieve fumo");
+async fn fumo_id_choices(
+    ctx: Context<'_>,
+    partial: &str,
+) -> Result<Vec<serenity::AutocompleteChoice>, Error> {
+    let filter = if partial.is_empty() {
+        doc! {}
+    } else {
+        let pattern = escape_regex(partial);
+        doc! { "$or": [
+            { "_id": { "$regex": format!("^{}", pattern) } },
+            { "caption": { "$regex": pattern, "$options": "i" } },
+        ] }
+    };
+    let fumos: Vec<Document> = ctx
+        .data()
+        .fumos_collection
+        .aggregate(vec![
+            doc! { "$match": filter },
+            doc! { "$project": { "caption": 1 } },
+            id_order_stage(),
+            // Newest first
+            doc! { "$sort": { "order": -1 } },
+            doc! { "$limit": 25 },
+        ])
+        .await?
+        .try_collect()
+        .await?;
+    Ok(fumos
+        .iter()
+        .filter_map(|fumo| {
+            let id = fumo.get_str("_id").ok()?;
+            let caption = fumo.get_str("caption").unwrap_or("No caption");
+            // Choice names are limited to 100 characters
+            let name: String = format!("{} · {}", id, caption).chars().take(100).collect();
+            Some(serenity::AutocompleteChoice::new(name, id))
+        })
+        .collect())
+}
 
-    let fumo: APIFumo = res.json().await.expect("Failed to parse fumo");
+/// Matches `text` literally in a mongo `$regex`
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
 
-    println!("{:?}", fumo.url.to_string());
+#[poise::command(prefix_command, slash_command)]
+pub async fn fumo(
+    ctx: Context<'_>,
+    #[description = "The id of the fumo you want to search for"]
+    #[autocomplete = "autocomplete_fumo_id"]
+    fumo: String,
+) -> Result<(), Error> {
+    // The API retries can take longer than the 3 seconds Discord waits for an answer
+    ctx.defer().await?;
+    let fumo = ctx.data().fumo_api.get(fumo.trim()).await?;
 
     let fumo = Fumo {
         _id: fumo._id.to_string(),
@@ -85,80 +193,398 @@ pub async fn fumo(
         image: fumo.url,
         source: fumo.source,
         credit: fumo.credit,
-        featured: Some("Reimu".to_string()),
+        featured: fumo.featured,
     };
     let embed = generate_fumo_embed(fumo);
     ctx.send(CreateReply::default().embed(embed)).await?;
     Ok(())
 }
 
+/// Kinds of media `/random` can be limited to
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum MediaFilter {
+    Image,
+    #[name = "GIF"]
+    Gif,
+    Video,
+}
+
+impl MediaFilter {
+    /// Value of the `type` field of the fumos collection, see `MediaType`
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Image => "image",
+            Self::Gif => "gif",
+            Self::Video => "video",
+        }
+    }
+}
+
 #[poise::command(prefix_command, slash_command)]
-pub async fn random(ctx: Context<'_>) -> Result<(), Error> {
+pub async fn random(
+    ctx: Context<'_>,
+    #[description = "Only the fumos featuring this character"]
+    #[autocomplete = "autocomplete_character"]
+    character: Option<String>,
+    #[description = "Only images, GIFs or videos"] media: Option<MediaFilter>,
+    #[description = "Only the fumos by this photographer"] credit: Option<String>,
+    #[description = "Only the fumos with, or without, a caption"] has_caption: Option<bool>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
     let data = ctx.data();
-    let client = data.web_client.clone();
+    let character = character
+        .as_deref()
+        .map(str::trim)
+        .filter(|c| !c.is_empty());
+    let credit = credit.as_deref().map(str::trim).filter(|c| !c.is_empty());
 
-    let res = client.get(format!("{}/random", data.fumo_api_endpoint));
-    let res = res.send().await.expect("Failed to retrieve fumo");
+    // The API picks from every fumo, the filters need the fields only the database has
+    let fumo =
+        if character.is_none() && media.is_none() && credit.is_none() && has_caption.is_none() {
+            let fumo = data.fumo_api.random().await?;
+            Fumo {
+                _id: fumo._id,
+                caption: fumo.caption,
+                image: fumo.url,
+                source: fumo.source,
+                credit: fumo.credit,
+                featured: fumo.featured,
+            }
+        } else {
+            let mut filters = Vec::new();
+            if let Some(character) = character {
+                filters.push(character_filter(data, character));
+            }
+            if let Some(media) = media {
+                filters.push(doc! { "type": media.as_str() });
+            }
+            if let Some(credit) = credit {
+                filters
+                    .push(doc! { "credit": { "$regex": escape_regex(credit), "$options": "i" } });
+            }
+            match has_caption {
+                Some(true) => filters.push(doc! { "caption": { "$nin": [Bson::Null, ""] } }),
+                Some(false) => filters.push(doc! { "caption": { "$in": [Bson::Null, ""] } }),
+                None => {}
+            }
+            sample_fumo(data, doc! { "$and": filters })
+                .await?
+                .ok_or_else(|| {
+                    Error::NotFound(
+                        "No fumo matches all of that, try again with fewer filters".to_owned(),
+                    )
+                })?
+        };
+    let embed = generate_fumo_embed(fumo);
+    ctx.send(CreateReply::default().embed(embed)).await?;
+    Ok(())
+}
 
-    let fumo: APIFumo = res.json().await.expect("Failed to parse fumo");
+/// Fumos featuring a character. Registered characters are also matched by id, the name
+/// is still needed for fumos added before the registry existed
+fn character_filter(data: &Data, character: &str) -> Document {
+    let registered = data
+        .characters
+        .read()
+        .unwrap()
+        .find(character)
+        .map(|c| (c._id.clone(), c.name.clone()));
+    match registered {
+        Some((id, name)) => doc! { "$or": [
+            { "characters": id },
+            { "featured": { "$regex": escape_regex(&name), "$options": "i" } },
+        ] },
+        None => doc! { "featured": { "$regex": escape_regex(character), "$options": "i" } },
+    }
+}
 
-    let fumo = Fumo {
-        _id: fumo._id,
-        caption: fumo.caption,
-        image: fumo.url,
-        source: fumo.source,
-        credit: fumo.credit,
-        featured: Some("Reimu".to_string()),
+/// Random fumo of the fumos collection matching the filter
+pub async fn sample_fumo(data: &Data, filter: Document) -> Result<Option<Fumo>, Error> {
+    let mut sample = data
+        .fumos_collection
+        .aggregate(vec![
+            doc! { "$match": filter },
+            doc! { "$sample": { "size": 1 } },
+        ])
+        .await?;
+    match sample.try_next().await? {
+        Some(fumo) => Fumo::from_document(&fumo)
+            .map(Some)
+            .ok_or_else(|| Error::NotFound("That fumo has no media".to_owned())),
+        None => Ok(None),
+    }
+}
+
+const FUMOS_PER_PAGE: u64 = 10;
+
+/// List the fumos page by page
+#[poise::command(prefix_command, slash_command)]
+pub async fn fumos(
+    ctx: Context<'_>,
+    #[description = "Page to show, starting at 1"]
+    #[min = 1]
+    page: Option<u64>,
+    #[description = "Only the fumos featuring this character"]
+    #[autocomplete = "autocomplete_character"]
+    featured: Option<String>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+    let api = &ctx.data().fumo_api;
+    let featured = featured
+        .as_deref()
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|f| ctx.data().canonical_character(f));
+    let featured = featured.as_deref();
+    let total = api.count(featured).await?;
+    let pages = total.div_ceil(FUMOS_PER_PAGE).max(1);
+    let page = page.unwrap_or(1).clamp(1, pages);
+    let fumos = match featured {
+        Some(featured) => api.search_featured(featured, page, FUMOS_PER_PAGE).await?,
+        None => api.list(page, FUMOS_PER_PAGE).await?,
     };
-    let embed = generate_fumo_embed(fumo);
+    if fumos.is_empty() {
+        return Err(Error::NotFound(match featured {
+            Some(featured) => format!("There are no fumos featuring {}", featured),
+            None => "There are no fumos yet".to_owned(),
+        }));
+    }
+
+    let lines: Vec<_> = fumos
+        .iter()
+        .map(|fumo| {
+            format!(
+                "[`{}`]({}) {}",
+                fumo._id,
+                fumo.url,
+                fumo.caption.as_deref().unwrap_or("No caption")
+            )
+        })
+        .collect();
+    let embed = serenity::CreateEmbed::new()
+        .title(match featured {
+            Some(featured) => format!("Fumos featuring {}", featured),
+            None => "Fumos".to_owned(),
+        })
+        .description(lines.join("\n"))
+        .footer(CreateEmbedFooter::new(format!(
+            "Page {}/{} · {} fumos · Use /fumo <id> to see one",
+            page, pages, total
+        )));
     ctx.send(CreateReply::default().embed(embed)).await?;
     Ok(())
 }
+
+/// Rehost approved submissions on the cdn and promote them to the fumos collection
 #[poise::command(prefix_command, slash_command)]
-pub async fn push(ctx: Context<'_>) -> Result<(), Error> {
-    if !ctx.data().curators.contains(&ctx.author().id) {
-        ctx.reply("You are not a curator").await?;
+pub async fn push(
+    ctx: Context<'_>,
+    #[description = "Only report what would be pushed, without touching the database"]
+    dry_run: Option<bool>,
+) -> Result<(), Error> {
+    ensure_curator(ctx).await?;
+    ctx.defer().await?;
+    let data = ctx.data();
+
+    // Collected upfront so the cursor doesn't time out while media is being uploaded
+    let approved: Vec<_> = data
+        .submissions_collection
+        .find(doc! { "status": SubmissionStatus::Approved })
+        .await?
+        .try_collect()
+        .await?;
+
+    if dry_run.unwrap_or(false) {
+        let mut lines = Vec::new();
+        for submission in &approved {
+            let already_present = data
+                .fumos_collection
+                .find_one(doc! { "_id": &submission._id })
+                .await?
+                .is_some();
+            lines.push(format!(
+                "- `{}`{}: {}",
+                submission._id,
+                if already_present {
+                    " (already in the database, would only be marked as promoted)"
+                } else {
+                    ""
+                },
+                submission.caption.as_deref().unwrap_or("No caption")
+            ));
+        }
+        let report = format!(
+            "Dry run, {} submissions would be pushed:\n{}",
+            approved.len(),
+            lines.join("\n")
+        );
+        ctx.reply(truncate_message(report)).await?;
         return Ok(());
     }
-    let fumos_collection = ctx.data().fumos_collection.clone();
-    let submissions_collection = ctx.data().submissions_collection.clone();
 
-    let mut approved_cur = submissions_collection
-        .find(doc! { "approved": true })
+    let mut pushed = 0;
+    let mut already_present = 0;
+    let mut failures = Vec::new();
+
+    // A submission is only removed once its fumo is safely stored,
+    // so running push again resumes where a failed run stopped
+    for submission in approved {
+        let id = submission._id.clone();
+        match promote_submission(data, submission, &ctx.author().id.to_string()).await {
+            Ok(PromotionOutcome::Inserted) => pushed += 1,
+            Ok(PromotionOutcome::AlreadyPresent) => already_present += 1,
+            Err(e) => {
+                println!("Failed to push submission {}: {:?}", id, e);
+                failures.push(format!("- `{}`: {}", id, e));
+            }
+        }
+    }
+
+    let mut report = format!("Pushed {} fumos to production database", pushed);
+    if already_present > 0 {
+        report += &format!(
+            "\n{} were already in the database and got marked as promoted",
+            already_present
+        );
+    }
+    if !failures.is_empty() {
+        report += &format!(
+            "\n{} failed, run push again to retry them:\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+    ctx.reply(truncate_message(report)).await?;
+    Ok(())
+}
+
+/// Compute the perceptual hash of fumos added before duplicate detection existed
+#[poise::command(prefix_command, slash_command)]
+pub async fn backfill_hashes(ctx: Context<'_>) -> Result<(), Error> {
+    ensure_curator(ctx).await?;
+    ctx.defer().await?;
+    let data = ctx.data();
+    let fumos = data.fumos_collection.clone_with_type::<Document>();
+
+    let missing: Vec<Document> = fumos
+        .find(doc! { "dhash": { "$exists": false } })
+        .projection(doc! { "image_url": 1, "url": 1, "type": 1 })
+        .await?
+        .try_collect()
         .await?;
 
-    let mut i = 0;
-
-    while let Some(submission) = approved_cur.try_next().await? {
-        i += 1;
-        let fumo = FumoDoc {
-            _id: submission._id.to_string(),
-            caption: submission.caption,
-            image_url: submission.image_url,
-            source: submission.source,
-            credit: submission.credit,
-            featured: submission.featured,
+    let mut hashed = 0;
+    let mut skipped = 0;
+    for fumo in &missing {
+        let id = fumo.get("_id").cloned().unwrap_or(Bson::Null);
+        let Some(url) = fumo_media_url(fumo) else {
+            skipped += 1;
+            continue;
+        };
+        if fumo.get_str("type") == Ok("video") {
+            skipped += 1;
+            continue;
+        }
+        let hash = match storage::download_media(&data.web_client, url).await {
+            Ok((bytes, _)) => duplicates::hash_image(bytes).await,
+            Err(e) => {
+                println!("Failed to download fumo {} for hashing: {}", id, e);
+                None
+            }
         };
-        fumos_collection.insert_one(fumo).await?;
-        submissions_collection
-            .delete_one(doc! { "_id": submission._id })
+        let Some(hash) = hash else {
+            skipped += 1;
+            continue;
+        };
+        fumos
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "dhash": media::format_hash(hash) } },
+            )
             .await?;
+        hashed += 1;
     }
-    ctx.reply(format!("Pushed {} fumos to production database", i))
-        .await?;
+
+    ctx.reply(format!(
+        "Hashed {} fumos, {} could not be hashed (videos or unreachable media)",
+        hashed, skipped
+    ))
+    .await?;
     Ok(())
 }
 
+/// Discord rejects messages longer than 2000 characters
+fn truncate_message(message: String) -> String {
+    if message.chars().count() <= 2000 {
+        return message;
+    }
+    message.chars().take(1997).collect::<String>() + "..."
+}
+
 pub fn generate_fumo_embed(fumo: Fumo) -> serenity::CreateEmbed {
     serenity::CreateEmbed::new()
         .title(format!("Fumo #{}", fumo._id))
-        .description(fumo.caption.unwrap_or_else(|| "No caption".to_string()))
+        .description(fumo.caption.unwrap_or_else(|| "No caption".to_owned()))
         .image(fumo.image)
         .footer(CreateEmbedFooter::new(format!(
             "Source: {}",
-            fumo.source.unwrap_or_else(|| "Unknown".to_string())
+            fumo.source.unwrap_or_else(|| "Unknown".to_owned())
         )))
         .author(CreateEmbedAuthor::new(
-            fumo.credit.unwrap_or_else(|| "Unknown".to_string()),
+            fumo.credit.unwrap_or_else(|| "Unknown".to_owned()),
         ))
+        .field(
+            "Featured",
+            fumo.featured.unwrap_or_else(|| "Unknown".to_owned()),
+            true,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_sort_in_submission_order() {
+        let mut ids = vec![
+            "1300000000000000000-1",
+            "812345678901234567",
+            "1300000000000000001",
+            "1300000000000000000",
+            "712345678901234567",
+        ];
+        ids.sort_by_key(|id| id_order_key(id));
+        assert_eq!(
+            ids,
+            [
+                "712345678901234567",
+                "812345678901234567",
+                "1300000000000000000",
+                "1300000000000000000-1",
+                "1300000000000000001",
+            ]
+        );
+    }
+
+    #[test]
+    fn media_url_falls_back_to_the_upload_script_field() {
+        let bot = doc! { "_id": "1", "image_url": "https://cdn/1.png", "url": "https://old/1.png" };
+        let script = doc! { "_id": "2", "url": "https://cdn/2.png" };
+        assert_eq!(fumo_media_url(&bot), Some("https://cdn/1.png"));
+        assert_eq!(fumo_media_url(&script), Some("https://cdn/2.png"));
+        assert_eq!(fumo_media_url(&doc! { "_id": "3" }), None);
+        assert_eq!(
+            Fumo::from_document(&script).unwrap().image,
+            "https://cdn/2.png"
+        );
+    }
+
+    #[test]
+    fn order_keys_pad_the_snowflake() {
+        assert_eq!(id_order_key("812345678901234567"), "00812345678901234567");
+        assert_eq!(
+            id_order_key("1300000000000000000-2"),
+            "01300000000000000000-2"
+        );
+    }
 }
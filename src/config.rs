@@ -0,0 +1,439 @@
+use crate::media::MediaPolicy;
+use serde::Deserialize;
+use serenity::all::ChannelId;
+use std::{env::var, fmt, path::PathBuf, time::Duration};
+
+/// Settings as written in the config file, every field can be left out
+/// and overridden by the environment variable next to it
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    /// `DISCORD_TOKEN`
+    discord_token: Option<String>,
+    /// `MONGO_URI`
+    mongo_uri: Option<String>,
+    /// `DATABASE_NAME`
+    database: Option<String>,
+    /// `PREFIX`
+    prefix: Option<String>,
+    /// `FUMOS_CHANNEL_ID`
+    fumos_channel_id: Option<u64>,
+    /// `FUMO_API_ENDPOINT`
+    fumo_api_endpoint: Option<String>,
+    /// `FUMO_API_TIMEOUT`, in seconds
+    fumo_api_timeout: Option<u64>,
+    /// `USERS_IN_BLACKLIST`, comma separated in the environment
+    users_in_blacklist: Option<Vec<String>>,
+    storage: RawStorage,
+    media: RawMedia,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawStorage {
+    /// `STORAGE_BACKEND`
+    backend: Option<String>,
+    /// `CDN_BASE_URL`
+    cdn_base_url: Option<String>,
+    /// `LOCAL_STORAGE_DIR`
+    local_dir: Option<PathBuf>,
+    /// `R2_ENDPOINT`
+    endpoint: Option<String>,
+    /// `R2_BUCKET`
+    bucket: Option<String>,
+    /// `R2_REGION`
+    region: Option<String>,
+    /// `R2_ACCESS_KEY_ID`
+    access_key_id: Option<String>,
+    /// `R2_SECRET_ACCESS_KEY`
+    secret_access_key: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct RawMedia {
+    /// `ALLOWED_MEDIA_TYPES`, comma separated in the environment
+    allowed_types: Option<Vec<String>>,
+    /// `MAX_MEDIA_SIZE`
+    max_size: Option<u64>,
+}
+
+pub struct Config {
+    pub discord_token: String,
+    pub mongo_uri: String,
+    pub database: String,
+    pub prefix: String,
+    /// Submission channel used before per guild settings, see `settings::GuildSettings`
+    pub fumos_channel_id: Option<ChannelId>,
+    pub fumo_api_endpoint: String,
+    /// Of every attempt of a fumo API request
+    pub fumo_api_timeout: Duration,
+    /// Users to move from the old environment variable to the blacklist collection
+    pub users_in_blacklist: Vec<String>,
+    pub storage: StorageConfig,
+    pub media: MediaPolicy,
+}
+
+pub enum StorageConfig {
+    S3 {
+        endpoint: String,
+        bucket: String,
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+        public_base_url: String,
+    },
+    Local {
+        root: PathBuf,
+        public_base_url: String,
+    },
+}
+
+/// Every problem found in the configuration, so they can all be fixed in one go
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads the file at `CONFIG_FILE` (`config.toml` by default, it's fine if it doesn't
+    /// exist) and overlays the environment variables on top of it
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut problems = Vec::new();
+        let path = var("CONFIG_FILE").unwrap_or_else(|_| "config.toml".to_owned());
+        let mut raw = match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                problems.push(format!("{}: {}", path, e));
+                RawConfig::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RawConfig::default(),
+            Err(e) => {
+                problems.push(format!("Can't read {}: {}", path, e));
+                RawConfig::default()
+            }
+        };
+        raw.overlay_env(&mut problems);
+        raw.validate(problems)
+    }
+}
+
+/// Comma separated list in an environment variable
+fn env_list(key: &str) -> Option<Vec<String>> {
+    var(key).ok().map(|list| {
+        list.split(',')
+            .map(|item| item.trim().to_owned())
+            .filter(|item| !item.is_empty())
+            .collect()
+    })
+}
+
+fn env_number(key: &str, problems: &mut Vec<String>) -> Option<u64> {
+    let value = var(key).ok()?;
+    match value.trim().parse() {
+        Ok(number) => Some(number),
+        Err(_) => {
+            problems.push(format!("{} is not a number: `{}`", key, value));
+            None
+        }
+    }
+}
+
+fn require<T>(value: Option<T>, name: &str, problems: &mut Vec<String>) -> Option<T> {
+    if value.is_none() {
+        problems.push(format!("{} is not set", name));
+    }
+    value
+}
+
+fn check_url(url: &str, name: &str, problems: &mut Vec<String>) {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        problems.push(format!("{} is not an http(s) url: `{}`", name, url));
+    }
+}
+
+impl RawConfig {
+    fn overlay_env(&mut self, problems: &mut Vec<String>) {
+        let string = |key: &str, field: &mut Option<String>| {
+            if let Some(value) = var(key).ok().filter(|v| !v.is_empty()) {
+                *field = Some(value);
+            }
+        };
+        string("DISCORD_TOKEN", &mut self.discord_token);
+        string("MONGO_URI", &mut self.mongo_uri);
+        string("DATABASE_NAME", &mut self.database);
+        string("PREFIX", &mut self.prefix);
+        string("FUMO_API_ENDPOINT", &mut self.fumo_api_endpoint);
+        if let Some(timeout) = env_number("FUMO_API_TIMEOUT", problems) {
+            self.fumo_api_timeout = Some(timeout);
+        }
+        if let Some(id) = env_number("FUMOS_CHANNEL_ID", problems) {
+            self.fumos_channel_id = Some(id);
+        }
+        if let Some(users) = env_list("USERS_IN_BLACKLIST") {
+            self.users_in_blacklist = Some(users);
+        }
+
+        let storage = &mut self.storage;
+        string("STORAGE_BACKEND", &mut storage.backend);
+        string("CDN_BASE_URL", &mut storage.cdn_base_url);
+        if let Ok(dir) = var("LOCAL_STORAGE_DIR") {
+            storage.local_dir = Some(dir.into());
+        }
+        string("R2_ENDPOINT", &mut storage.endpoint);
+        string("R2_BUCKET", &mut storage.bucket);
+        string("R2_REGION", &mut storage.region);
+        string("R2_ACCESS_KEY_ID", &mut storage.access_key_id);
+        string("R2_SECRET_ACCESS_KEY", &mut storage.secret_access_key);
+
+        if let Some(types) = env_list("ALLOWED_MEDIA_TYPES") {
+            self.media.allowed_types = Some(types);
+        }
+        if let Some(size) = env_number("MAX_MEDIA_SIZE", problems) {
+            self.media.max_size = Some(size);
+        }
+    }
+
+    fn validate(self, mut problems: Vec<String>) -> Result<Config, ConfigError> {
+        let p = &mut problems;
+        let discord_token = require(self.discord_token, "DISCORD_TOKEN", p);
+        let mongo_uri = require(self.mongo_uri, "MONGO_URI", p);
+        let fumos_channel_id = match self.fumos_channel_id {
+            Some(0) => {
+                p.push("FUMOS_CHANNEL_ID can't be 0".to_owned());
+                None
+            }
+            id => id.map(ChannelId::new),
+        };
+
+        let database = self.database.unwrap_or_else(|| "fumo-api".to_owned());
+        if database.is_empty() {
+            p.push("DATABASE_NAME can't be empty".to_owned());
+        }
+        let prefix = self.prefix.unwrap_or_else(|| ")".to_owned());
+        if prefix.trim().is_empty() {
+            p.push("PREFIX can't be empty".to_owned());
+        }
+        let fumo_api_endpoint = self
+            .fumo_api_endpoint
+            .unwrap_or_else(|| "http://localhost:6969".to_owned())
+            .trim_end_matches('/')
+            .to_owned();
+        check_url(&fumo_api_endpoint, "FUMO_API_ENDPOINT", p);
+        let fumo_api_timeout = self.fumo_api_timeout.unwrap_or(10);
+        if fumo_api_timeout == 0 {
+            p.push("FUMO_API_TIMEOUT can't be 0".to_owned());
+        }
+
+        let users_in_blacklist = self.users_in_blacklist.unwrap_or_default();
+        for user in &users_in_blacklist {
+            if user.parse::<u64>().is_err() {
+                p.push(format!(
+                    "USERS_IN_BLACKLIST has an invalid user id: `{}`",
+                    user
+                ));
+            }
+        }
+
+        let storage = self.storage;
+        let public_base_url = storage
+            .cdn_base_url
+            .unwrap_or_else(|| "https://cdn.nosesisaid.com".to_owned());
+        check_url(&public_base_url, "CDN_BASE_URL", p);
+        let storage = match storage.backend.as_deref() {
+            Some("local") => Some(StorageConfig::Local {
+                root: storage.local_dir.unwrap_or_else(|| "./cdn".into()),
+                public_base_url,
+            }),
+            Some("s3") | None => {
+                let endpoint = require(storage.endpoint, "R2_ENDPOINT", p);
+                if let Some(endpoint) = &endpoint {
+                    check_url(endpoint, "R2_ENDPOINT", p);
+                }
+                let access_key_id = require(storage.access_key_id, "R2_ACCESS_KEY_ID", p);
+                let secret_access_key =
+                    require(storage.secret_access_key, "R2_SECRET_ACCESS_KEY", p);
+                match (endpoint, access_key_id, secret_access_key) {
+                    (Some(endpoint), Some(access_key_id), Some(secret_access_key)) => {
+                        Some(StorageConfig::S3 {
+                            endpoint,
+                            bucket: storage
+                                .bucket
+                                .unwrap_or_else(|| "nosesisaid-cdn".to_owned()),
+                            region: storage.region.unwrap_or_else(|| "auto".to_owned()),
+                            access_key_id,
+                            secret_access_key,
+                            public_base_url,
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            Some(other) => {
+                p.push(format!(
+                    "Unknown STORAGE_BACKEND `{}`, expected `s3` or `local`",
+                    other
+                ));
+                None
+            }
+        };
+
+        let allowed_mime_types = self.media.allowed_types.unwrap_or_else(|| {
+            MediaPolicy::DETECTED_TYPES
+                .iter()
+                .map(|&mime_type| mime_type.to_owned())
+                .collect()
+        });
+        if allowed_mime_types.is_empty() {
+            p.push("ALLOWED_MEDIA_TYPES can't be empty".to_owned());
+        }
+        for mime_type in &allowed_mime_types {
+            if !MediaPolicy::DETECTED_TYPES.contains(&mime_type.as_str()) {
+                p.push(format!(
+                    "ALLOWED_MEDIA_TYPES has `{}`, which is never detected, expected some of {}",
+                    mime_type,
+                    MediaPolicy::DETECTED_TYPES.join(", ")
+                ));
+            }
+        }
+        let max_size = self.media.max_size.unwrap_or(MediaPolicy::DEFAULT_MAX_SIZE);
+        if max_size == 0 {
+            p.push("MAX_MEDIA_SIZE can't be 0".to_owned());
+        }
+
+        match (discord_token, mongo_uri, storage) {
+            (Some(discord_token), Some(mongo_uri), Some(storage)) if problems.is_empty() => {
+                Ok(Config {
+                    discord_token,
+                    mongo_uri,
+                    database,
+                    prefix,
+                    fumos_channel_id,
+                    fumo_api_endpoint,
+                    fumo_api_timeout: Duration::from_secs(fumo_api_timeout),
+                    users_in_blacklist,
+                    storage,
+                    media: MediaPolicy {
+                        allowed_mime_types,
+                        max_size,
+                    },
+                })
+            }
+            _ => Err(ConfigError(problems)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(raw: &str) -> Vec<String> {
+        let raw: RawConfig = toml::from_str(raw).unwrap();
+        match raw.validate(Vec::new()) {
+            Ok(_) => Vec::new(),
+            Err(ConfigError(problems)) => problems,
+        }
+    }
+
+    #[test]
+    fn reports_every_missing_setting() {
+        assert_eq!(
+            problems(""),
+            [
+                "DISCORD_TOKEN is not set",
+                "MONGO_URI is not set",
+                "R2_ENDPOINT is not set",
+                "R2_ACCESS_KEY_ID is not set",
+                "R2_SECRET_ACCESS_KEY is not set",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_invalid_setting() {
+        let problems = problems(
+            r#"
+            discord_token = "token"
+            mongo_uri = "mongodb://localhost"
+            prefix = " "
+            fumos_channel_id = 0
+            fumo_api_endpoint = "localhost:6969"
+            fumo_api_timeout = 0
+            users_in_blacklist = ["688476559019212805", "someone"]
+
+            [storage]
+            backend = "ftp"
+            cdn_base_url = "cdn.nosesisaid.com"
+
+            [media]
+            allowed_types = ["image/png", "image/avif"]
+            max_size = 0
+            "#,
+        );
+        let expected = [
+            "FUMOS_CHANNEL_ID can't be 0",
+            "PREFIX can't be empty",
+            "FUMO_API_ENDPOINT is not an http(s) url",
+            "FUMO_API_TIMEOUT can't be 0",
+            "USERS_IN_BLACKLIST has an invalid user id: `someone`",
+            "CDN_BASE_URL is not an http(s) url",
+            "Unknown STORAGE_BACKEND `ftp`",
+            "ALLOWED_MEDIA_TYPES has `image/avif`",
+            "MAX_MEDIA_SIZE can't be 0",
+        ];
+        assert_eq!(problems.len(), expected.len(), "{:#?}", problems);
+        for (problem, expected) in problems.iter().zip(expected) {
+            assert!(problem.starts_with(expected), "{} != {}", problem, expected);
+        }
+    }
+
+    #[test]
+    fn keeps_the_problems_found_while_reading() {
+        let raw = RawConfig {
+            discord_token: Some("token".to_owned()),
+            ..Default::default()
+        };
+        let Err(ConfigError(problems)) =
+            raw.validate(vec!["MAX_MEDIA_SIZE is not a number: `big`".to_owned()])
+        else {
+            panic!("the configuration is invalid");
+        };
+        assert_eq!(problems[0], "MAX_MEDIA_SIZE is not a number: `big`");
+        assert!(problems.contains(&"MONGO_URI is not set".to_owned()));
+    }
+
+    #[test]
+    fn defaults_fill_a_minimal_config() {
+        let raw: RawConfig = toml::from_str(
+            r#"
+            discord_token = "token"
+            mongo_uri = "mongodb://localhost"
+
+            [storage]
+            backend = "local"
+            "#,
+        )
+        .unwrap();
+        let config = raw.validate(Vec::new()).unwrap();
+        assert_eq!(config.database, "fumo-api");
+        assert_eq!(config.prefix, ")");
+        assert_eq!(config.fumo_api_timeout, Duration::from_secs(10));
+        assert_eq!(config.media.allowed_mime_types, MediaPolicy::DETECTED_TYPES);
+        assert!(matches!(config.storage, StorageConfig::Local { .. }));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<RawConfig>("discord_tokn = \"token\"").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{Context, Data, Error};
+use mongodb::{
+    bson::{doc, Document},
+    Collection,
+};
+use poise::{serenity_prelude as serenity, CreateReply};
+use serde::{Deserialize, Serialize};
+use serenity::{futures::TryStreamExt, GuildId, RoleId, UserId};
+use std::collections::HashSet;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CuratorDoc {
+    /// Discord user id
+    _id: String,
+    added_by: String,
+    added_at: i64,
+}
+
+/// In memory copy of the curators stored in mongo, updated by the curators commands
+#[derive(Default)]
+pub struct CuratorRoster {
+    users: HashSet<UserId>,
+}
+
+impl CuratorRoster {
+    pub async fn load(curators: &Collection<CuratorDoc>) -> Result<Self, Error> {
+        let mut roster = Self::default();
+        let mut curators = curators.find(doc! {}).await?;
+        while let Some(curator) = curators.try_next().await? {
+            roster.users.insert(curator._id.parse()?);
+        }
+        Ok(roster)
+    }
+}
+
+/// Curator hardcoded before the roster existed
+const LEGACY_CURATOR: u64 = 688476559019212805;
+
+/// Adds the curator hardcoded by older versions to the roster, once, so a fresh deploy
+/// isn't left without anyone able to review. Removing them with `/curators remove` sticks.
+/// Returns whether they were added
+pub async fn seed_legacy(
+    curators: &Collection<CuratorDoc>,
+    migrations: &Collection<Document>,
+) -> Result<bool, Error> {
+    let marker = doc! { "_id": "legacy_curator" };
+    if migrations.find_one(marker.clone()).await?.is_some() {
+        return Ok(false);
+    }
+    curators
+        .update_one(
+            doc! { "_id": LEGACY_CURATOR.to_string() },
+            doc! { "$setOnInsert": {
+                "added_by": "environment",
+                "added_at": chrono::Utc::now().timestamp(),
+            } },
+        )
+        .upsert(true)
+        .await?;
+    migrations.insert_one(marker).await?;
+    Ok(true)
+}
+
+impl Data {
+    /// Whether someone is a curator, either directly or through the curator role of the guild
+    pub fn is_curator(&self, user: UserId, guild: Option<GuildId>, roles: &[RoleId]) -> bool {
+        self.curators.read().unwrap().users.contains(&user)
+            || self
+                .guild_settings(guild)
+                .and_then(|settings| settings.curator_role())
+                .is_some_and(|role| roles.contains(&role))
+    }
+}
+
+/// Fails with a permission error when the author of a command isn't a curator
+pub async fn ensure_curator(ctx: Context<'_>) -> Result<(), Error> {
+    let roles = ctx
+        .author_member()
+        .await
+        .map(|member| member.roles.clone())
+        .unwrap_or_default();
+    if ctx
+        .data()
+        .is_curator(ctx.author().id, ctx.guild_id(), &roles)
+    {
+        Ok(())
+    } else {
+        Err(Error::Permission("You are not a curator".to_owned()))
+    }
+}
+
+/// Manage who can review submissions
+#[poise::command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    subcommands("add", "remove", "list"),
+    subcommand_required
+)]
+pub async fn curators(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Make someone a curator
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "The user that will be able to review submissions"] user: serenity::User,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    data.curators_collection
+        .update_one(
+            doc! { "_id": user.id.to_string() },
+            doc! { "$setOnInsert": {
+                "added_by": ctx.author().id.to_string(),
+                "added_at": chrono::Utc::now().timestamp(),
+            } },
+        )
+        .upsert(true)
+        .await?;
+    data.curators.write().unwrap().users.insert(user.id);
+    ctx.reply(format!("<@{}> is now a curator", user.id))
+        .await?;
+    Ok(())
+}
+
+/// Remove someone from the curators
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "The user that won't be able to review submissions anymore"]
+    user: serenity::User,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let deleted = data
+        .curators_collection
+        .delete_one(doc! { "_id": user.id.to_string() })
+        .await?
+        .deleted_count;
+    data.curators.write().unwrap().users.remove(&user.id);
+    if deleted == 0 {
+        ctx.reply(format!("<@{}> was not a curator", user.id))
+            .await?;
+    } else {
+        ctx.reply(format!("<@{}> is no longer a curator", user.id))
+            .await?;
+    }
+    Ok(())
+}
+
+/// List the curators
+#[poise::command(prefix_command, slash_command, owners_only)]
+pub async fn list(ctx: Context<'_>) -> Result<(), Error> {
+    let users: Vec<String> = {
+        let roster = ctx.data().curators.read().unwrap();
+        roster.users.iter().map(|u| format!("- <@{}>", u)).collect()
+    };
+    let role = ctx
+        .data()
+        .guild_settings(ctx.guild_id())
+        .and_then(|settings| settings.curator_role());
+    let mut description = if users.is_empty() {
+        "No curators yet".to_owned()
+    } else {
+        users.join("\n")
+    };
+    if let Some(role) = role {
+        description += &format!("\nAnd every member with <@&{}>", role);
+    }
+    ctx.send(
+        CreateReply::default().embed(
+            serenity::CreateEmbed::new()
+                .title("Curators")
+                .description(description),
+        ),
+    )
+    .await?;
+    Ok(())
+}
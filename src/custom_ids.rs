@@ -0,0 +1,332 @@
+use std::fmt;
+
+/// Bumped when the meaning of an action changes, so buttons of old review cards
+/// aren't handled as if they were created by the current version
+const VERSION: &str = "v1";
+
+/// What a review component does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReviewAction {
+    /// Every attachment of the message
+    Approve,
+    Reject,
+    AddInfo,
+    /// Attachments picked in a select menu
+    ApproveItems,
+    RejectItems,
+}
+
+impl ReviewAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Approve => "approve",
+            Self::Reject => "reject",
+            Self::AddInfo => "add_info",
+            Self::ApproveItems => "approve_items",
+            Self::RejectItems => "reject_items",
+        }
+    }
+
+    fn parse(action: &str) -> Option<Self> {
+        Some(match action {
+            "approve" => Self::Approve,
+            "reject" => Self::Reject,
+            "add_info" => Self::AddInfo,
+            "approve_items" => Self::ApproveItems,
+            "reject_items" => Self::RejectItems,
+            _ => return None,
+        })
+    }
+}
+
+/// Custom id of a review component, `v1:<action>:<submission id>`.
+/// The submission is the first attachment of the message, which stands for the whole group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReviewComponentId {
+    pub action: ReviewAction,
+    /// `None` for components sent before ids were encoded, which only had the action
+    pub submission_id: Option<String>,
+}
+
+impl ReviewComponentId {
+    pub fn new(action: ReviewAction, submission_id: &str) -> Self {
+        Self {
+            action,
+            submission_id: Some(submission_id.to_owned()),
+        }
+    }
+
+    /// `None` for custom ids that aren't review components, or come from an unknown version
+    pub fn parse(custom_id: &str) -> Option<Self> {
+        let mut parts = custom_id.splitn(3, ':');
+        match (parts.next()?, parts.next(), parts.next()) {
+            (VERSION, Some(action), Some(id)) if !id.is_empty() => {
+                Some(Self::new(ReviewAction::parse(action)?, id))
+            }
+            (legacy, None, None) => Some(Self {
+                action: ReviewAction::parse(legacy)?,
+                submission_id: None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ReviewComponentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.submission_id {
+            Some(id) => write!(f, "{}:{}:{}", VERSION, self.action.as_str(), id),
+            None => f.write_str(self.action.as_str()),
+        }
+    }
+}
+
+/// What a `/search` results component does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchAction {
+    Previous,
+    Next,
+    /// Select menu with the results of the page
+    Open,
+}
+
+impl SearchAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Previous => "previous",
+            Self::Next => "next",
+            Self::Open => "open",
+        }
+    }
+
+    fn parse(action: &str) -> Option<Self> {
+        Some(match action {
+            "previous" => Self::Previous,
+            "next" => Self::Next,
+            "open" => Self::Open,
+            _ => return None,
+        })
+    }
+}
+
+/// Custom id of a `/search` results component, `v1:search:<action>:<page>:<query>`.
+/// The results aren't stored anywhere, the query and the page shown are enough to
+/// render the next ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchComponentId {
+    pub action: SearchAction,
+    /// Page of the message the component is attached to, starting at 1
+    pub page: u64,
+    /// Last, it can contain `:`
+    pub query: String,
+}
+
+impl SearchComponentId {
+    pub fn new(action: SearchAction, page: u64, query: &str) -> Self {
+        Self {
+            action,
+            page,
+            query: query.to_owned(),
+        }
+    }
+
+    pub fn parse(custom_id: &str) -> Option<Self> {
+        let mut parts = custom_id.splitn(5, ':');
+        match (
+            parts.next()?,
+            parts.next()?,
+            parts.next()?,
+            parts.next()?,
+            parts.next()?,
+        ) {
+            (VERSION, "search", action, page, query) => Some(Self::new(
+                SearchAction::parse(action)?,
+                page.parse().ok()?,
+                query,
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SearchComponentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:search:{}:{}:{}",
+            VERSION,
+            self.action.as_str(),
+            self.page,
+            self.query
+        )
+    }
+}
+
+/// What a `/gallery` button does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GalleryAction {
+    First,
+    Previous,
+    Next,
+    Last,
+    Random,
+    /// Opens a modal asking for the page
+    Jump,
+}
+
+impl GalleryAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::First => "first",
+            Self::Previous => "previous",
+            Self::Next => "next",
+            Self::Last => "last",
+            Self::Random => "random",
+            Self::Jump => "jump",
+        }
+    }
+
+    fn parse(action: &str) -> Option<Self> {
+        Some(match action {
+            "first" => Self::First,
+            "previous" => Self::Previous,
+            "next" => Self::Next,
+            "last" => Self::Last,
+            "random" => Self::Random,
+            "jump" => Self::Jump,
+            _ => return None,
+        })
+    }
+}
+
+/// Custom id of a `/gallery` button, `v1:gallery:<action>:<fumo id>`. The fumo shown is
+/// the cursor the previous and next ones are found from, so the buttons keep working
+/// after a restart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GalleryComponentId {
+    pub action: GalleryAction,
+    pub cursor: String,
+}
+
+impl GalleryComponentId {
+    pub fn new(action: GalleryAction, cursor: &str) -> Self {
+        Self {
+            action,
+            cursor: cursor.to_owned(),
+        }
+    }
+
+    pub fn parse(custom_id: &str) -> Option<Self> {
+        let mut parts = custom_id.splitn(4, ':');
+        match (parts.next()?, parts.next()?, parts.next()?, parts.next()?) {
+            (VERSION, "gallery", action, cursor) if !cursor.is_empty() => {
+                Some(Self::new(GalleryAction::parse(action)?, cursor))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GalleryComponentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:gallery:{}:{}",
+            VERSION,
+            self.action.as_str(),
+            self.cursor
+        )
+    }
+}
+
+/// Custom id of any component the bot creates, see `interactions::handle_component`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComponentId {
+    Review(ReviewComponentId),
+    Search(SearchComponentId),
+    Gallery(GalleryComponentId),
+}
+
+impl ComponentId {
+    /// `None` for custom ids the bot didn't create, or from an unknown version
+    pub fn parse(custom_id: &str) -> Option<Self> {
+        SearchComponentId::parse(custom_id)
+            .map(Self::Search)
+            .or_else(|| GalleryComponentId::parse(custom_id).map(Self::Gallery))
+            .or_else(|| ReviewComponentId::parse(custom_id).map(Self::Review))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn review_ids_round_trip() {
+        for action in [
+            ReviewAction::Approve,
+            ReviewAction::Reject,
+            ReviewAction::AddInfo,
+            ReviewAction::ApproveItems,
+            ReviewAction::RejectItems,
+        ] {
+            let id = ReviewComponentId::new(action, "1300000000000000000-1");
+            assert_eq!(
+                ComponentId::parse(&id.to_string()),
+                Some(ComponentId::Review(id))
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_review_ids_have_no_submission() {
+        let id = ReviewComponentId::parse("approve").unwrap();
+        assert_eq!(id.action, ReviewAction::Approve);
+        assert_eq!(id.submission_id, None);
+        assert_eq!(id.to_string(), "approve");
+        assert_eq!(ReviewComponentId::parse("publish"), None);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        assert_eq!(ComponentId::parse("v2:approve:1300000000000000000"), None);
+        assert_eq!(ComponentId::parse("v2:search:next:1:reimu"), None);
+        assert_eq!(
+            ComponentId::parse("v2:gallery:next:1300000000000000000"),
+            None
+        );
+        assert_eq!(ComponentId::parse("v1:approve:"), None);
+    }
+
+    #[test]
+    fn search_queries_can_contain_colons() {
+        let id = SearchComponentId::new(SearchAction::Next, 3, "source: twitter:cirno");
+        let parsed = ComponentId::parse(&id.to_string());
+        assert_eq!(parsed, Some(ComponentId::Search(id)));
+    }
+
+    #[test]
+    fn gallery_ids_round_trip() {
+        for action in [
+            GalleryAction::First,
+            GalleryAction::Previous,
+            GalleryAction::Next,
+            GalleryAction::Last,
+            GalleryAction::Random,
+            GalleryAction::Jump,
+        ] {
+            let id = GalleryComponentId::new(action, "812345678901234567");
+            assert_eq!(
+                ComponentId::parse(&id.to_string()),
+                Some(ComponentId::Gallery(id))
+            );
+        }
+    }
+
+    #[test]
+    fn longest_search_id_fits_in_discord_limit() {
+        let query = "a".repeat(crate::search::MAX_QUERY_LENGTH);
+        let id = SearchComponentId::new(SearchAction::Previous, 99999, &query);
+        assert!(id.to_string().chars().count() <= 100, "{}", id);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{commands::fumo_media_url, media, submissions::SubmissionStatus, Data, Error};
+use mongodb::bson::{doc, Document};
+use poise::serenity_prelude::futures::TryStreamExt;
+
+/// Hashes at least this similar are considered the same picture
+pub const DUPLICATE_SIMILARITY: f64 = 0.85;
+
+pub struct DuplicateMatch {
+    pub id: String,
+    /// Image of an existing fumo or message of a submission still in review
+    pub link: String,
+    pub similarity: f64,
+}
+
+/// Looks for fumos and pending or approved submissions that look like the hashed image
+pub async fn find_duplicates(
+    data: &Data,
+    hash: u64,
+    exclude_message_id: &str,
+) -> Result<Vec<DuplicateMatch>, Error> {
+    let mut matches = Vec::new();
+
+    let mut fumos = data
+        .fumos_collection
+        .clone_with_type::<Document>()
+        .find(doc! { "dhash": { "$exists": true } })
+        .projection(doc! { "dhash": 1, "image_url": 1, "url": 1 })
+        .await?;
+    while let Some(fumo) = fumos.try_next().await? {
+        let Some(similarity) = compare(&fumo, hash) else {
+            continue;
+        };
+        matches.push(DuplicateMatch {
+            id: fumo.get_str("_id").unwrap_or_default().to_owned(),
+            link: fumo_media_url(&fumo).unwrap_or_default().to_owned(),
+            similarity,
+        });
+    }
+
+    let mut submissions = data
+        .submissions_collection
+        .clone_with_type::<Document>()
+        .find(doc! {
+            "dhash": { "$exists": true },
+            "status": { "$in": [SubmissionStatus::Pending, SubmissionStatus::Approved] },
+            "message_id": { "$ne": exclude_message_id },
+        })
+        .projection(doc! {
+            "dhash": 1,
+            "message_id": 1,
+            "guild_id": 1,
+            "channel_id": 1,
+            "image_url": 1,
+        })
+        .await?;
+    while let Some(submission) = submissions.try_next().await? {
+        let Some(similarity) = compare(&submission, hash) else {
+            continue;
+        };
+        matches.push(DuplicateMatch {
+            id: submission.get_str("_id").unwrap_or_default().to_owned(),
+            link: submission_link(data, &submission),
+            similarity,
+        });
+    }
+
+    matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    Ok(matches)
+}
+
+/// Message the submission was sent in, like `SubmissionDoc::link`. Submissions stored before
+/// the channel was recorded were all sent in `FUMOS_CHANNEL_ID`
+fn submission_link(data: &Data, submission: &Document) -> String {
+    let channel = submission
+        .get_str("channel_id")
+        .ok()
+        .map(str::to_owned)
+        .or_else(|| data.config.fumos_channel_id.map(|c| c.to_string()));
+    match (channel, submission.get_str("message_id")) {
+        (Some(channel), Ok(message)) => format!(
+            "https://discord.com/channels/{}/{}/{}",
+            submission.get_str("guild_id").unwrap_or("@me"),
+            channel,
+            message
+        ),
+        _ => submission
+            .get_str("image_url")
+            .unwrap_or_default()
+            .to_owned(),
+    }
+}
+
+fn compare(document: &Document, hash: u64) -> Option<f64> {
+    let other = media::parse_hash(document.get_str("dhash").ok()?)?;
+    let similarity = media::similarity(hash, other);
+    (similarity >= DUPLICATE_SIMILARITY).then_some(similarity)
+}
+
+/// Hashes an image off the async runtime, decoding is CPU heavy
+pub async fn hash_image(bytes: Vec<u8>) -> Option<u64> {
+    tokio::task::spawn_blocking(move || media::dhash(&bytes))
+        .await
+        .ok()
+        .flatten()
+}
@@ -0,0 +1,107 @@
+use crate::submissions::TransitionError;
+use poise::serenity_prelude as serenity;
+use std::fmt;
+
+/// Error returned by every command and handler. The variant decides what the user is told,
+/// the details are only logged
+#[derive(Debug)]
+pub enum Error {
+    /// The fumo API failed
+    Api(String),
+    /// Downloading media, from the Discord CDN or wherever a fumo is hosted, failed
+    Download(String),
+    Database(mongodb::error::Error),
+    Discord(Box<serenity::Error>),
+    /// Rehosting media in the bucket or the local directory failed
+    Storage(String),
+    /// The user asked for something that can't be done, shown to them as is
+    Validation(String),
+    /// Shown to the user as is
+    Permission(String),
+    /// Shown to the user as is
+    NotFound(String),
+}
+
+impl Error {
+    /// Message for the user, which doesn't leak internal details
+    pub fn user_message(&self) -> String {
+        match self {
+            Self::Api(_) => "The fumo API isn't answering right now, try again later".to_owned(),
+            Self::Download(_) => "Couldn't download the media, try again later".to_owned(),
+            Self::Database(_) => "Couldn't reach the database, try again later".to_owned(),
+            Self::Discord(_) => "Discord didn't accept that, try again later".to_owned(),
+            Self::Storage(_) => "Couldn't store the fumo media, try again later".to_owned(),
+            Self::Validation(message) | Self::Permission(message) | Self::NotFound(message) => {
+                message.clone()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Api(e) => write!(f, "Api error: {}", e),
+            Self::Download(e) => write!(f, "Download error: {}", e),
+            Self::Database(e) => write!(f, "Database error: {}", e),
+            Self::Discord(e) => write!(f, "Discord error: {}", e),
+            Self::Storage(e) => write!(f, "Storage error: {}", e),
+            Self::Validation(e) => write!(f, "Validation error: {}", e),
+            Self::Permission(e) => write!(f, "Permission error: {}", e),
+            Self::NotFound(e) => write!(f, "Not found: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Database(e) => Some(e),
+            Self::Discord(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for Error {
+    fn from(e: mongodb::error::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl From<serenity::Error> for Error {
+    fn from(e: serenity::Error) -> Self {
+        Self::Discord(Box::new(e))
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Api(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Storage(e.to_string())
+    }
+}
+
+/// Discord ids are stored as strings
+impl From<std::num::ParseIntError> for Error {
+    fn from(e: std::num::ParseIntError) -> Self {
+        Self::Validation(format!("Invalid Discord id: {}", e))
+    }
+}
+
+impl From<TransitionError> for Error {
+    fn from(e: TransitionError) -> Self {
+        match e {
+            TransitionError::Database(e) => Self::Database(e),
+            TransitionError::NotFound => Self::NotFound(e.to_string()),
+            TransitionError::Invalid { .. } | TransitionError::Locked(_) => {
+                Self::Validation(e.to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use crate::Error;
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+
+const ATTEMPTS: u32 = 3;
+/// Doubled after every failed attempt
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct APIFumo {
+    pub _id: String,
+    pub caption: Option<String>,
+    pub url: String,
+    pub source: Option<String>,
+    pub credit: Option<String>,
+    pub featured: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Count {
+    count: u64,
+}
+
+/// Client of the [Fumo-API](https://github.com/nosesisaid/fumo-api)
+pub struct FumoApi {
+    client: reqwest::Client,
+    base_url: String,
+    timeout: Duration,
+}
+
+/// Fumo ids are Discord snowflakes, followed by `-<index>` for the
+/// other attachments of a submission with several of them
+pub fn is_valid_id(id: &str) -> bool {
+    let (snowflake, index) = match id.split_once('-') {
+        Some((snowflake, index)) => (snowflake, Some(index)),
+        None => (id, None),
+    };
+    let digits = |s: &str, max: usize| {
+        !s.is_empty() && s.len() <= max && s.bytes().all(|b| b.is_ascii_digit())
+    };
+    digits(snowflake, 20) && index.is_none_or(|index| digits(index, 2))
+}
+
+impl FumoApi {
+    /// `timeout` applies to every attempt of a request, not to all of them together
+    pub fn new(client: reqwest::Client, base_url: &str, timeout: Duration) -> Self {
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            timeout,
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Result<APIFumo, Error> {
+        if !is_valid_id(id) {
+            return Err(Error::Validation(format!(
+                "`{}` is not a fumo id, they look like `1234567890123456789`",
+                id
+            )));
+        }
+        self.request(&format!("/fumo/{}", id), &[])
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("No fumo with id {}", id)))
+    }
+
+    pub async fn random(&self) -> Result<APIFumo, Error> {
+        self.request("/random", &[])
+            .await?
+            .ok_or_else(|| Error::NotFound("There are no fumos yet".to_owned()))
+    }
+
+    /// Fumos of the page, starting at 1. Past the last page it's empty
+    pub async fn list(&self, page: u64, per_page: u64) -> Result<Vec<APIFumo>, Error> {
+        self.page(page, per_page, None).await
+    }
+
+    /// Page of the fumos featuring the character
+    pub async fn search_featured(
+        &self,
+        featured: &str,
+        page: u64,
+        per_page: u64,
+    ) -> Result<Vec<APIFumo>, Error> {
+        self.page(page, per_page, Some(featured)).await
+    }
+
+    async fn page(
+        &self,
+        page: u64,
+        per_page: u64,
+        featured: Option<&str>,
+    ) -> Result<Vec<APIFumo>, Error> {
+        let mut query = vec![("page", page.to_string()), ("limit", per_page.to_string())];
+        if let Some(featured) = featured {
+            query.push(("featured", featured.to_owned()));
+        }
+        Ok(self.request("/fumos", &query).await?.unwrap_or_default())
+    }
+
+    /// How many fumos there are, or how many feature the character
+    pub async fn count(&self, featured: Option<&str>) -> Result<u64, Error> {
+        let query: Vec<_> = featured
+            .map(|featured| ("featured", featured.to_owned()))
+            .into_iter()
+            .collect();
+        let count: Option<Count> = self.request("/fumos/count", &query).await?;
+        Ok(count.map_or(0, |count| count.count))
+    }
+
+    /// `None` on 404. Timeouts, connection errors and 5xx are retried with an exponential backoff
+    async fn request<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<Option<T>, Error> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut backoff = FIRST_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let request = self.client.get(&url).query(query).timeout(self.timeout);
+            let retryable = match request.send().await {
+                Ok(res) if res.status() == StatusCode::NOT_FOUND => return Ok(None),
+                Ok(res) if res.status().is_server_error() => {
+                    Error::Api(format!("{} answered {}", url, res.status()))
+                }
+                Ok(res) => return Ok(Some(res.error_for_status()?.json().await?)),
+                Err(e) if e.is_timeout() || e.is_connect() => e.into(),
+                Err(e) => return Err(e.into()),
+            };
+            if attempt == ATTEMPTS {
+                return Err(retryable);
+            }
+            println!(
+                "Fumo API request {} failed (attempt {}/{}), retrying in {:?}: {}",
+                url, attempt, ATTEMPTS, backoff, retryable
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    const FUMO: &str = r#"{"_id":"123456789012345678","caption":"Reimu at the beach","url":"https://cdn.nosesisaid.com/123456789012345678.png","source":null,"credit":"Someone","featured":"Reimu Hakurei"}"#;
+
+    enum Reply {
+        Json(u16, String),
+        /// Never answers, so the request times out
+        Hang,
+    }
+
+    /// Fumo API answering each connection with the next reply,
+    /// returns a client for it and the request targets it got
+    async fn mock_api(replies: Vec<Reply>) -> (FumoApi, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for reply in replies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buffer = vec![0; 4096];
+                let read = socket.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]).into_owned();
+                let target = request.split_whitespace().nth(1).unwrap_or_default();
+                received.lock().unwrap().push(target.to_owned());
+                match reply {
+                    Reply::Json(status, body) => {
+                        let response = format!(
+                            "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            status,
+                            body.len(),
+                            body
+                        );
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    }
+                    Reply::Hang => {
+                        tokio::spawn(async move {
+                            tokio::time::sleep(Duration::from_secs(10)).await;
+                            drop(socket);
+                        });
+                    }
+                }
+            }
+        });
+        let api = FumoApi::new(reqwest::Client::new(), &url, Duration::from_millis(200));
+        (api, requests)
+    }
+
+    fn requests(requests: &Arc<Mutex<Vec<String>>>) -> Vec<String> {
+        requests.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn get_fetches_the_fumo() {
+        let (api, received) = mock_api(vec![Reply::Json(200, FUMO.to_owned())]).await;
+        let fumo = api.get("123456789012345678").await.unwrap();
+        assert_eq!(fumo._id, "123456789012345678");
+        assert_eq!(fumo.featured.as_deref(), Some("Reimu Hakurei"));
+        assert_eq!(requests(&received), ["/fumo/123456789012345678"]);
+    }
+
+    #[tokio::test]
+    async fn get_of_a_missing_fumo_is_not_found() {
+        let (api, _) = mock_api(vec![Reply::Json(404, "{}".to_owned())]).await;
+        let error = api.get("123456789012345678-1").await.unwrap_err();
+        assert!(matches!(error, Error::NotFound(_)), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn get_rejects_invalid_ids_without_a_request() {
+        let (api, received) = mock_api(Vec::new()).await;
+        for id in ["../random", "abc", "123-456", "", "123456789012345678901"] {
+            let error = api.get(id).await.unwrap_err();
+            assert!(matches!(error, Error::Validation(_)), "{}: {:?}", id, error);
+        }
+        assert!(requests(&received).is_empty());
+    }
+
+    #[tokio::test]
+    async fn random_fetches_a_fumo() {
+        let (api, received) = mock_api(vec![Reply::Json(200, FUMO.to_owned())]).await;
+        assert_eq!(api.random().await.unwrap()._id, "123456789012345678");
+        assert_eq!(requests(&received), ["/random"]);
+    }
+
+    #[tokio::test]
+    async fn random_without_fumos_is_not_found() {
+        let (api, _) = mock_api(vec![Reply::Json(404, "{}".to_owned())]).await;
+        let error = api.random().await.unwrap_err();
+        assert!(matches!(error, Error::NotFound(_)), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn list_sends_the_page() {
+        let page = format!("[{},{}]", FUMO, FUMO);
+        let (api, received) = mock_api(vec![Reply::Json(200, page)]).await;
+        assert_eq!(api.list(2, 10).await.unwrap().len(), 2);
+        assert_eq!(requests(&received), ["/fumos?page=2&limit=10"]);
+    }
+
+    #[tokio::test]
+    async fn list_past_the_last_page_is_empty() {
+        let (api, _) = mock_api(vec![Reply::Json(404, "{}".to_owned())]).await;
+        assert!(api.list(99, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_featured_sends_the_character() {
+        let page = format!("[{}]", FUMO);
+        let (api, received) = mock_api(vec![Reply::Json(200, page)]).await;
+        let fumos = api.search_featured("Reimu Hakurei", 1, 5).await.unwrap();
+        assert_eq!(fumos.len(), 1);
+        assert_eq!(
+            requests(&received),
+            ["/fumos?page=1&limit=5&featured=Reimu+Hakurei"]
+        );
+    }
+
+    #[tokio::test]
+    async fn count_reads_the_count() {
+        let (api, received) = mock_api(vec![
+            Reply::Json(200, r#"{"count":42}"#.to_owned()),
+            Reply::Json(200, r#"{"count":9}"#.to_owned()),
+        ])
+        .await;
+        assert_eq!(api.count(None).await.unwrap(), 42);
+        assert_eq!(api.count(Some("Cirno")).await.unwrap(), 9);
+        assert_eq!(
+            requests(&received),
+            ["/fumos/count", "/fumos/count?featured=Cirno"]
+        );
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let (api, received) = mock_api(vec![
+            Reply::Json(500, "{}".to_owned()),
+            Reply::Json(503, "{}".to_owned()),
+            Reply::Json(200, FUMO.to_owned()),
+        ])
+        .await;
+        assert!(api.random().await.is_ok());
+        assert_eq!(requests(&received).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn server_errors_fail_after_the_last_attempt() {
+        let (api, received) = mock_api(vec![
+            Reply::Json(500, "{}".to_owned()),
+            Reply::Json(500, "{}".to_owned()),
+            Reply::Json(500, "{}".to_owned()),
+        ])
+        .await;
+        let error = api.random().await.unwrap_err();
+        assert!(matches!(error, Error::Api(_)), "{:?}", error);
+        assert_eq!(requests(&received).len(), ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_retried() {
+        let (api, received) = mock_api(vec![
+            Reply::Hang,
+            Reply::Hang,
+            Reply::Json(200, FUMO.to_owned()),
+        ])
+        .await;
+        assert!(api.get("123456789012345678").await.is_ok());
+        assert_eq!(requests(&received).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (api, received) = mock_api(vec![Reply::Json(400, "{}".to_owned())]).await;
+        let error = api.random().await.unwrap_err();
+        assert!(matches!(error, Error::Api(_)), "{:?}", error);
+        assert_eq!(requests(&received).len(), 1);
+    }
+}
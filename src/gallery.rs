@@ -0,0 +1,202 @@
+//! `/gallery`, the fumos one at a time in submission order, see `id_order_key`. Nothing is
+//! kept in memory, every button carries the id of the fumo shown, see `GalleryComponentId`
+use crate::{
+    commands::{generate_fumo_embed, id_order_key, id_order_stage, sample_fumo, Fumo},
+    custom_ids::{GalleryAction, GalleryComponentId},
+    Context, Data, Error,
+};
+use ::serenity::all::{
+    ComponentInteraction, CreateActionRow, CreateButton, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditMessage,
+};
+use mongodb::bson::{doc, Document};
+use poise::{serenity_prelude as serenity, CreateReply};
+use serenity::futures::TryStreamExt;
+
+#[derive(Debug, poise::Modal)]
+#[name = "Go to page"]
+struct JumpModal {
+    #[name = "Page"]
+    #[max_length = 10]
+    page: String,
+}
+
+/// Browse the fumos one at a time
+#[poise::command(prefix_command, slash_command)]
+pub async fn gallery(
+    ctx: Context<'_>,
+    #[description = "Page to start at, the first one by default"]
+    #[min = 1]
+    page: Option<u64>,
+) -> Result<(), Error> {
+    let data = ctx.data();
+    let fumo = nth_fumo(data, page.unwrap_or(1)).await?;
+    let (content, embed, components) = gallery_page(data, fumo).await?;
+    ctx.send(
+        CreateReply::default()
+            .content(content)
+            .embed(embed)
+            .components(components),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Fumo at a page, starting at 1. Pages past the end show the last fumo
+async fn nth_fumo(data: &Data, page: u64) -> Result<Fumo, Error> {
+    let total = data.fumos_collection.count_documents(doc! {}).await?;
+    if total == 0 {
+        return Err(Error::NotFound("There are no fumos yet".to_owned()));
+    }
+    let fumo = first_in_order(data, doc! {}, true, page.clamp(1, total) - 1).await?;
+    to_fumo(fumo)
+}
+
+/// First fumo matching the filter in submission order, or the last one when not `ascending`.
+/// The filter can use `order`, see `id_order_stage`
+async fn fumo_at(data: &Data, filter: Document, ascending: bool) -> Result<Option<Fumo>, Error> {
+    let fumo = first_in_order(data, filter, ascending, 0).await?;
+    fumo.map(|fumo| to_fumo(Some(fumo))).transpose()
+}
+
+async fn first_in_order(
+    data: &Data,
+    filter: Document,
+    ascending: bool,
+    skip: u64,
+) -> Result<Option<Document>, Error> {
+    let mut fumos = data
+        .fumos_collection
+        .aggregate(vec![
+            id_order_stage(),
+            doc! { "$match": filter },
+            doc! { "$sort": { "order": if ascending { 1 } else { -1 } } },
+            doc! { "$skip": skip as i64 },
+            doc! { "$limit": 1 },
+        ])
+        .await?;
+    Ok(fumos.try_next().await?)
+}
+
+fn to_fumo(fumo: Option<Document>) -> Result<Fumo, Error> {
+    fumo.as_ref()
+        .and_then(Fumo::from_document)
+        .ok_or_else(|| Error::NotFound("That fumo doesn't exist anymore".to_owned()))
+}
+
+/// Position of the fumo, the embed and the buttons
+async fn gallery_page(
+    data: &Data,
+    fumo: Fumo,
+) -> Result<(String, CreateEmbed, Vec<CreateActionRow>), Error> {
+    let fumos = &data.fumos_collection;
+    let total = fumos.count_documents(doc! {}).await?;
+    let before: Vec<Document> = fumos
+        .aggregate(vec![
+            id_order_stage(),
+            doc! { "$match": { "order": { "$lt": id_order_key(&fumo._id) } } },
+            doc! { "$count": "before" },
+        ])
+        .await?
+        .try_collect()
+        .await?;
+    let before = before
+        .first()
+        .and_then(|count| count.get_i32("before").ok())
+        .unwrap_or(0);
+    let page = before as u64 + 1;
+    let custom_id = |action| GalleryComponentId::new(action, &fumo._id).to_string();
+    let button = |action, label: &str| {
+        CreateButton::new(custom_id(action))
+            .label(label)
+            .style(serenity::ButtonStyle::Secondary)
+    };
+    let components = vec![
+        CreateActionRow::Buttons(vec![
+            button(GalleryAction::First, "First").disabled(page <= 1),
+            button(GalleryAction::Previous, "Previous").disabled(page <= 1),
+            button(GalleryAction::Next, "Next").disabled(page >= total),
+            button(GalleryAction::Last, "Last").disabled(page >= total),
+            button(GalleryAction::Random, "Random"),
+        ]),
+        CreateActionRow::Buttons(vec![button(GalleryAction::Jump, "Go to page")]),
+    ];
+    let content = format!("Fumo {} of {}", page, total);
+    Ok((content, generate_fumo_embed(fumo), components))
+}
+
+/// Called by the router for the gallery buttons, anyone can use them
+pub async fn handle_component(
+    ctx: &serenity::Context,
+    component: &ComponentInteraction,
+    data: &Data,
+    component_id: GalleryComponentId,
+) -> Result<(), Error> {
+    let cursor = id_order_key(&component_id.cursor);
+    let fumo = match component_id.action {
+        GalleryAction::First => fumo_at(data, doc! {}, true).await?,
+        GalleryAction::Previous => {
+            fumo_at(data, doc! { "order": { "$lt": cursor } }, false).await?
+        }
+        GalleryAction::Next => fumo_at(data, doc! { "order": { "$gt": cursor } }, true).await?,
+        GalleryAction::Last => fumo_at(data, doc! {}, false).await?,
+        GalleryAction::Random => sample_fumo(data, doc! {}).await?,
+        GalleryAction::Jump => return jump(ctx, component, data).await,
+    };
+    let fumo = fumo.ok_or_else(|| Error::NotFound("There are no fumos there".to_owned()))?;
+    let (content, embed, components) = gallery_page(data, fumo).await?;
+    component
+        .create_response(
+            ctx,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .embed(embed)
+                    .components(components),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Asks for a page in a modal and shows it in the gallery message
+async fn jump(
+    ctx: &serenity::Context,
+    component: &ComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let Some(modal) = poise::execute_modal_on_component_interaction::<JumpModal>(
+        Box::new(ctx.clone()),
+        component.clone(),
+        None,
+        None,
+    )
+    .await?
+    else {
+        // The modal was closed or timed out
+        return Ok(());
+    };
+    let page = match modal.page.trim().parse::<u64>() {
+        Ok(page) if page > 0 => page,
+        _ => {
+            return Err(Error::Validation(format!(
+                "`{}` is not a page, they start at 1",
+                modal.page.trim()
+            )))
+        }
+    };
+    let fumo = nth_fumo(data, page).await?;
+    let (content, embed, components) = gallery_page(data, fumo).await?;
+    // The modal submission was already acknowledged, the message is edited directly
+    (*component.message)
+        .clone()
+        .edit(
+            ctx,
+            EditMessage::new()
+                .content(content)
+                .embed(embed)
+                .components(components),
+        )
+        .await?;
+    Ok(())
+}
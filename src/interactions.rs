@@ -0,0 +1,546 @@
+//! Buttons and menus of the bot. Every review card action is a handler function,
+//! the router checks its permission and finds the submission before calling it.
+//! Other features handle their own components, the router only dispatches to them
+use crate::{
+    custom_ids::{ComponentId, ReviewAction, ReviewComponentId},
+    gallery, review_card, review_components, search,
+    submissions::{edit_info, transition, SubmissionInfo, SubmissionStatus, TransitionError},
+    Data, Error, SubmissionDoc,
+};
+use ::serenity::all::{
+    ComponentInteraction, ComponentInteractionDataKind, CreateEmbed,
+    CreateInteractionResponseFollowup, CreateInteractionResponseMessage, CreateMessage, Message,
+    UserId,
+};
+use mongodb::bson::doc;
+use poise::serenity_prelude as serenity;
+use serenity::futures::TryStreamExt;
+
+#[derive(Debug, poise::Modal)]
+#[name = "Submission info"]
+struct MoreInfoModal {
+    credit: Option<String>,
+    source: Option<String>,
+    caption: Option<String>,
+    featured: Option<String>, // plushie featured in the image
+}
+
+impl From<SubmissionInfo> for MoreInfoModal {
+    fn from(info: SubmissionInfo) -> Self {
+        Self {
+            credit: info.credit,
+            source: info.source,
+            caption: info.caption,
+            featured: info.featured,
+        }
+    }
+}
+
+impl From<MoreInfoModal> for SubmissionInfo {
+    fn from(modal: MoreInfoModal) -> Self {
+        Self {
+            caption: modal.caption,
+            source: modal.source,
+            credit: modal.credit,
+            featured: modal.featured,
+            characters: Vec::new(),
+        }
+        .normalized()
+    }
+}
+
+#[derive(Debug, poise::Modal)]
+#[name = "Reject fumo submission"]
+struct RejectModal {
+    #[name = "Reason (sent to the submitter)"]
+    #[paragraph]
+    #[max_length = 500]
+    reason: Option<String>,
+}
+
+/// Who can use a component
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Permission {
+    Curator,
+    SubmitterOrCurator,
+}
+
+impl ReviewAction {
+    fn permission(self) -> Permission {
+        match self {
+            Self::Approve | Self::Reject | Self::ApproveItems | Self::RejectItems => {
+                Permission::Curator
+            }
+            Self::AddInfo => Permission::SubmitterOrCurator,
+        }
+    }
+}
+
+/// What the router resolved for an action handler
+struct Review<'a> {
+    ctx: &'a serenity::Context,
+    component: &'a ComponentInteraction,
+    data: &'a Data,
+    /// Review card of the submission, even when the component is on the acknowledgement
+    review_msg: Message,
+    /// First attachment of the reviewed message, see `ReviewComponentId`
+    submission: SubmissionDoc,
+    is_curator: bool,
+}
+
+impl Review<'_> {
+    /// Ids of every attachment of the reviewed message
+    async fn group_ids(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .data
+            .submissions_collection
+            .distinct("_id", doc! { "message_id": &self.submission.message_id })
+            .await?
+            .into_iter()
+            .filter_map(|id| id.as_str().map(|id| id.to_owned()))
+            .collect())
+    }
+
+    /// Attachments picked in a select menu
+    fn selected_ids(&self) -> Vec<String> {
+        match &self.component.data.kind {
+            ComponentInteractionDataKind::StringSelect { values } => values.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Entry point for every component interaction, errors are logged and the user gets
+/// an ephemeral message instead of an interaction that silently fails
+pub async fn handle_component(
+    ctx: &serenity::Context,
+    component: &ComponentInteraction,
+    data: &Data,
+) {
+    let Some(component_id) = ComponentId::parse(&component.data.custom_id) else {
+        return;
+    };
+    let handled = match component_id {
+        ComponentId::Review(component_id) => route(ctx, component, data, component_id).await,
+        ComponentId::Search(component_id) => {
+            search::handle_component(ctx, component, data, component_id).await
+        }
+        ComponentId::Gallery(component_id) => {
+            gallery::handle_component(ctx, component, data, component_id).await
+        }
+    };
+    if let Err(e) = handled {
+        println!(
+            "Error handling component `{}` used by {}: {}",
+            component.data.custom_id, component.user.id, e
+        );
+        if let Err(e) = reply_ephemeral(ctx, component, &e.user_message()).await {
+            println!("Error while handling error: {}", e);
+        }
+    }
+}
+
+async fn route(
+    ctx: &serenity::Context,
+    component: &ComponentInteraction,
+    data: &Data,
+    component_id: ReviewComponentId,
+) -> Result<(), Error> {
+    let Some(submission) =
+        find_reviewed_submission(data, &component_id, &component.message).await?
+    else {
+        return reply_ephemeral(ctx, component, "This submission doesn't exist anymore").await;
+    };
+    // Submitters use the button of the acknowledgement, the card is still the one kept up to date
+    let review_msg = match submission.review_card() {
+        Some((channel_id, message_id)) if message_id != component.message.id => {
+            channel_id.message(ctx, message_id).await?
+        }
+        _ => (*component.message).clone(),
+    };
+
+    let is_curator = data.is_curator(
+        component.user.id,
+        component.guild_id,
+        component
+            .member
+            .as_ref()
+            .map(|m| m.roles.as_slice())
+            .unwrap_or_default(),
+    );
+    let is_submitter = component.user.id.to_string() == submission.discord_submitter_id;
+    let denied = match component_id.action.permission() {
+        Permission::Curator if !is_curator => Some("You are not a curator, you can't review fumos"),
+        Permission::SubmitterOrCurator if !is_curator && !is_submitter => {
+            Some("Only the submitter and curators can edit this submission")
+        }
+        // Approved submissions get pushed as they are, so what curators approved can't change
+        Permission::SubmitterOrCurator
+            if !is_curator && submission.status != SubmissionStatus::Pending =>
+        {
+            Some("This submission was already reviewed, only curators can edit it now")
+        }
+        _ => None,
+    };
+    if let Some(denied) = denied {
+        return reply_ephemeral(ctx, component, denied).await;
+    }
+
+    let mut review = Review {
+        ctx,
+        component,
+        data,
+        review_msg,
+        submission,
+        is_curator,
+    };
+    match component_id.action {
+        ReviewAction::Approve => approve(&mut review).await,
+        ReviewAction::Reject => reject(&mut review).await,
+        ReviewAction::ApproveItems => approve_items(&mut review).await,
+        ReviewAction::RejectItems => reject_items(&mut review).await,
+        ReviewAction::AddInfo => add_info(&mut review).await,
+    }
+}
+
+/// Answers the user privately, as a follow up when a handler already acknowledged the
+/// interaction (by opening a modal for example)
+async fn reply_ephemeral(
+    ctx: &serenity::Context,
+    component: &ComponentInteraction,
+    content: &str,
+) -> Result<(), Error> {
+    let response = component
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            ),
+        )
+        .await;
+    if response.is_err() {
+        component
+            .create_followup(
+                ctx,
+                CreateInteractionResponseFollowup::new()
+                    .content(content)
+                    .ephemeral(true),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Answers the user privately once a modal acknowledged the interaction
+async fn followup_ephemeral(
+    ctx: &serenity::Context,
+    component: &ComponentInteraction,
+    content: &str,
+) -> Result<(), Error> {
+    component
+        .create_followup(
+            ctx,
+            CreateInteractionResponseFollowup::new()
+                .content(content)
+                .ephemeral(true),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Submission a review component was clicked for, looked up from the database so the
+/// buttons keep working after the submission message is deleted
+async fn find_reviewed_submission(
+    data: &Data,
+    component_id: &ReviewComponentId,
+    review_msg: &Message,
+) -> Result<Option<SubmissionDoc>, Error> {
+    let submissions = &data.submissions_collection;
+    if let Some(id) = &component_id.submission_id {
+        return Ok(submissions.find_one(doc! { "_id": id }).await?);
+    }
+    // Components sent before their custom id had the submission
+    if let Some(submission) = submissions
+        .find_one(doc! { "review_message_id": review_msg.id.to_string() })
+        .await?
+    {
+        return Ok(Some(submission));
+    }
+    // Even older reviews only reply to the submission
+    match review_msg
+        .message_reference
+        .as_ref()
+        .and_then(|r| r.message_id)
+    {
+        Some(message_id) => Ok(submissions
+            .find_one(doc! { "message_id": message_id.to_string() })
+            .await?),
+        None => Ok(None),
+    }
+}
+
+/// Renders the review card of a message again from the database, keeping the
+/// submitter and possible duplicates of the original card
+async fn refresh_review_card(review: &mut Review<'_>) -> Result<(), Error> {
+    let group: Vec<SubmissionDoc> = review
+        .data
+        .submissions_collection
+        .find(doc! { "message_id": &review.submission.message_id })
+        .await?
+        .try_collect()
+        .await?;
+    let review_msg = &mut review.review_msg;
+    let mut embeds = review_card(&group);
+    if let (Some(first), Some(author)) = (
+        embeds.first_mut(),
+        review_msg.embeds.first().and_then(|e| e.author.clone()),
+    ) {
+        *first = first.clone().author(author.into());
+    }
+    embeds.extend(
+        review_msg
+            .embeds
+            .iter()
+            .filter(|e| e.title.as_deref() == Some("Possible duplicates"))
+            .cloned()
+            .map(CreateEmbed::from),
+    );
+    // Nothing left to review once every attachment got rejected
+    let done = group
+        .iter()
+        .all(|s| s.status.next_statuses().is_empty() || s.status == SubmissionStatus::Rejected);
+    review_msg
+        .edit(
+            review.ctx,
+            serenity::EditMessage::new()
+                .embeds(embeds)
+                .components(review_components(&group, done)),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn approve(review: &mut Review<'_>) -> Result<(), Error> {
+    let ids = review.group_ids().await?;
+    approve_submissions(review, ids).await
+}
+
+async fn reject(review: &mut Review<'_>) -> Result<(), Error> {
+    let ids = review.group_ids().await?;
+    reject_submissions(review, ids).await
+}
+
+async fn approve_items(review: &mut Review<'_>) -> Result<(), Error> {
+    let ids = review.selected_ids();
+    approve_submissions(review, ids).await
+}
+
+async fn reject_items(review: &mut Review<'_>) -> Result<(), Error> {
+    let ids = review.selected_ids();
+    reject_submissions(review, ids).await
+}
+
+async fn approve_submissions(review: &mut Review<'_>, ids: Vec<String>) -> Result<(), Error> {
+    let (ctx, component, data) = (review.ctx, review.component, review.data);
+    let mut approved = Vec::new();
+    let mut skipped = Vec::new();
+    for id in ids {
+        match transition(
+            &data.submissions_collection,
+            &id,
+            SubmissionStatus::Approved,
+            &component.user.id.to_string(),
+            None,
+            None,
+        )
+        .await
+        {
+            Ok(submission) => approved.push(submission),
+            Err(e @ TransitionError::Invalid { .. }) => skipped.push(format!("`{}`: {}", id, e)),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let Some(first) = approved.first() else {
+        return reply_ephemeral(ctx, component, &skipped.join("\n")).await;
+    };
+    refresh_review_card(review).await?;
+    let ids = approved
+        .iter()
+        .map(|s| format!("`{}`", s._id))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let announcement = format!(
+        "<@{}> Your fumo submission has been approved 🎉. You can check it out with id {}",
+        first.discord_submitter_id, ids
+    );
+    match first.acknowledgement() {
+        Some((channel, acknowledgement)) => {
+            reply_ephemeral(ctx, component, &format!("Approved {}", ids)).await?;
+            channel
+                .send_message(
+                    ctx,
+                    CreateMessage::new()
+                        .content(announcement)
+                        .reference_message((channel, acknowledgement)),
+                )
+                .await?;
+        }
+        None => {
+            component
+                .create_response(
+                    ctx,
+                    serenity::CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new().content(announcement),
+                    ),
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn reject_submissions(review: &mut Review<'_>, ids: Vec<String>) -> Result<(), Error> {
+    let (ctx, component, data) = (review.ctx, review.component, review.data);
+    let Some(RejectModal { reason }) =
+        poise::execute_modal_on_component_interaction::<RejectModal>(
+            Box::new(ctx.clone()),
+            component.clone(),
+            None,
+            None,
+        )
+        .await?
+    else {
+        // The curator closed the modal or it timed out
+        return Ok(());
+    };
+
+    let mut rejected = Vec::new();
+    let mut skipped = Vec::new();
+    for id in ids {
+        match transition(
+            &data.submissions_collection,
+            &id,
+            SubmissionStatus::Rejected,
+            &component.user.id.to_string(),
+            reason.clone(),
+            None,
+        )
+        .await
+        {
+            Ok(submission) => rejected.push(submission),
+            Err(e @ TransitionError::Invalid { .. }) => skipped.push(format!("`{}`: {}", id, e)),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let Some(first) = rejected.first() else {
+        return followup_ephemeral(ctx, component, &skipped.join("\n")).await;
+    };
+
+    refresh_review_card(review).await?;
+
+    let ids = rejected
+        .iter()
+        .map(|s| format!("`{}`", s._id))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let announcement = CreateMessage::new().content(format!(
+        "<@{}> Your fumo submission {} has been denied 😟.{}",
+        first.discord_submitter_id,
+        ids,
+        reason
+            .as_ref()
+            .map(|r| format!("\nReason: {}", r))
+            .unwrap_or_default()
+    ));
+    match first.acknowledgement() {
+        Some((channel, acknowledgement)) => {
+            followup_ephemeral(ctx, component, &format!("Rejected {}", ids)).await?;
+            channel
+                .send_message(
+                    ctx,
+                    announcement.reference_message((channel, acknowledgement)),
+                )
+                .await?;
+        }
+        None => {
+            component
+                .channel_id
+                .send_message(ctx, announcement.reference_message(&review.review_msg))
+                .await?;
+        }
+    }
+
+    let submitter: UserId = first.discord_submitter_id.parse()?;
+    // The review card can be in a channel the submitter can't see
+    let dm = CreateMessage::new().content(format!(
+        "Your fumo submission {} ({}) was rejected by a curator.\nReason: {}",
+        first.link().unwrap_or_else(|| review.review_msg.link()),
+        ids,
+        reason.as_deref().unwrap_or("No reason given")
+    ));
+    if let Err(e) = submitter.direct_message(ctx, dm).await {
+        // Users can have their DMs closed, the public reply is enough then
+        println!("Failed to DM rejection reason to {}: {}", submitter, e);
+    }
+    Ok(())
+}
+
+async fn add_info(review: &mut Review<'_>) -> Result<(), Error> {
+    let (ctx, component, data) = (review.ctx, review.component, review.data);
+    let Some(modal) = poise::execute_modal_on_component_interaction::<MoreInfoModal>(
+        Box::new(ctx.clone()),
+        component.clone(),
+        Some(review.submission.info().into()),
+        None,
+    )
+    .await?
+    else {
+        // The modal was closed or timed out
+        return Ok(());
+    };
+    let mut info = SubmissionInfo::from(modal);
+    let unknown = data.resolve_featured(&mut info);
+
+    // The same info is shared by every attachment of the message
+    let mut edited = 0;
+    let mut skipped = Vec::new();
+    for id in review.group_ids().await? {
+        match edit_info(
+            &data.submissions_collection,
+            &id,
+            &info,
+            &component.user.id.to_string(),
+            review.is_curator,
+        )
+        .await
+        {
+            Ok(Some(_)) => edited += 1,
+            Ok(None) => {}
+            Err(e @ TransitionError::Database(_)) => return Err(e.into()),
+            Err(e) => skipped.push(format!("`{}`: {}", id, e)),
+        }
+    }
+    if edited > 0 {
+        refresh_review_card(review).await?;
+    }
+
+    let mut content = if edited > 0 {
+        "Submission info updated".to_owned()
+    } else {
+        "Nothing changed".to_owned()
+    };
+    if !unknown.is_empty() {
+        content += &format!(
+            "\nNot in the character registry, kept as written: {}",
+            unknown.join(", ")
+        );
+    }
+    if !skipped.is_empty() {
+        content += &format!("\n{}", skipped.join("\n"));
+    }
+    followup_ephemeral(ctx, component, &content).await
+}
@@ -1,48 +1,96 @@
 #![warn(clippy::str_to_string)]
 
+mod blacklist;
+mod characters;
 mod commands;
+mod config;
+mod curators;
+mod custom_ids;
+mod duplicates;
+mod error;
+mod fumo_api;
+mod gallery;
+mod interactions;
+mod media;
+mod promotion;
+mod search;
+mod settings;
+mod storage;
+mod submissions;
 
 use ::serenity::all::{
-    ChannelId, ComponentInteraction, CreateButton, CreateInteractionResponseMessage, CreateMessage,
-    Message, UserId,
+    ChannelId, CreateActionRow, CreateButton, CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter,
+    CreateMessage, CreateSelectMenu, CreateSelectMenuKind, CreateSelectMenuOption, GuildId,
+    MessageId, UserId,
 };
-use commands::Fumo;
+use blacklist::BlacklistDoc;
+use characters::{CharacterDoc, CharacterRegistry};
+use config::{Config, StorageConfig};
+use curators::{CuratorDoc, CuratorRoster};
+use custom_ids::{ReviewAction, ReviewComponentId};
 use dotenv::dotenv;
-use env_logger;
-use lazy_static::lazy_static;
-use mongodb::{
-    bson::{doc, Bson, Document},
-    Client as MongoClient, Collection as MongoCollection,
-};
+use fumo_api::FumoApi;
+use media::MediaType;
+use mongodb::{bson::doc, Collection as MongoCollection};
 use poise::serenity_prelude as serenity;
-use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use std::{env::var, sync::Arc, time::Duration};
+use serenity::futures::TryStreamExt;
+use settings::GuildSettings;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use storage::{FumoStorage, LocalStorage, S3Storage};
+use submissions::{
+    transition, StatusTransition, SubmissionInfo, SubmissionStatus, TransitionError,
+};
 
 // Types used by all command functions
-type Error = Box<dyn std::error::Error + Send + Sync>;
+use error::Error;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
 // Custom user data passed to all command functions
 pub struct Data {
+    config: Config,
     fumos_collection: MongoCollection<FumoDoc>,
     submissions_collection: MongoCollection<SubmissionDoc>,
-    fumo_api_endpoint: String,
     web_client: reqwest::Client,
-    curators: Vec<UserId>,
-}
-
-#[derive(Debug, poise::Modal)]
-struct MoreInfoModal {
-    credit: Option<String>,
-    source: Option<String>,
-    caption: Option<String>,
-    featured: Option<String>, // plushie featured in the image
+    fumo_api: FumoApi,
+    storage: Box<dyn FumoStorage>,
+    supports_transactions: bool,
+    curators_collection: MongoCollection<CuratorDoc>,
+    /// Cache of the collection above, see `Data::is_curator`
+    curators: RwLock<CuratorRoster>,
+    blacklist_collection: MongoCollection<BlacklistDoc>,
+    /// Cache of `blacklist_collection`, see `Data::is_blacklisted`
+    blacklist: RwLock<HashMap<UserId, BlacklistDoc>>,
+    guild_settings_collection: MongoCollection<GuildSettings>,
+    /// Cache of `guild_settings_collection`, see `Data::guild_settings`
+    guilds: RwLock<HashMap<GuildId, GuildSettings>>,
+    characters_collection: MongoCollection<CharacterDoc>,
+    /// Cache of `characters_collection`, see `Data::resolve_featured`
+    characters: RwLock<CharacterRegistry>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SubmissionDoc {
     _id: String,
+    /// Discord message the attachment was sent in, shared by every attachment of that message
+    message_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    guild_id: Option<String>,
+    /// Submission channel the message was sent in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    channel_id: Option<String>,
+    /// Public reply to the submitter, only sent when the review happens in another channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    acknowledgement_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    review_channel_id: Option<String>,
+    /// Bot message with the review buttons, in the review channel of the guild if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    review_message_id: Option<String>,
     image_url: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     caption: Option<String>,
@@ -52,8 +100,25 @@ pub struct SubmissionDoc {
     credit: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     featured: Option<String>,
-    approved: bool,
-    discarted: bool,
+    /// Ids of the registered characters in `featured`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    characters: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<MediaType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    /// In bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_size: Option<u64>,
+    /// Perceptual hash of the image, see `media::dhash`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dhash: Option<String>,
+    status: SubmissionStatus,
+    /// Every status change the submission went through, oldest first
+    #[serde(default)]
+    history: Vec<StatusTransition>,
     discord_submitter_id: String,
     time_of_submission: i64,
 }
@@ -70,18 +135,19 @@ pub struct FumoDoc {
     credit: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     featured: Option<String>,
-}
-
-lazy_static! {
-    static ref FUMOS_CHANNEL_ID: ChannelId = var("FUMOS_CHANNEL_ID")
-        .expect("FUMOS_CHANNEL_ID NOT SET")
-        .parse()
-        .expect("FUMOS_CHANNEL_ID is not a valid Channel ID");
-    static ref USERS_IN_BLACKLIST: Vec<UserId> = var("USERS_IN_BLACKLIST")
-        .expect("USERSINBLACKLIST must be set")
-        .split(',')
-        .map(|s| s.parse().expect("Invalid user ID in USERSINBLACKLIST"))
-        .collect();
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    characters: Vec<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<MediaType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    /// In bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dhash: Option<String>,
 }
 
 async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
@@ -89,9 +155,30 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
     // They are many errors that can occur, so we only handle the ones we want to customize
     // and forward the rest to the default handler
     match error {
-        poise::FrameworkError::Setup { error, .. } => panic!("Failed to start bot: {:?}", error),
+        poise::FrameworkError::Setup { error, .. } => fatal("Failed to start bot", error),
         poise::FrameworkError::Command { error, ctx, .. } => {
-            println!("Error in command `{}`: {:?}", ctx.command().name, error,);
+            println!("Error in command `{}`: {}", ctx.command().name, error);
+            let reply = poise::CreateReply::default()
+                .content(error.user_message())
+                .ephemeral(true);
+            if let Err(e) = ctx.send(reply).await {
+                println!("Error while handling error: {}", e)
+            }
+        }
+        poise::FrameworkError::EventHandler {
+            error, ctx, event, ..
+        } => {
+            println!(
+                "Error handling {} event: {}",
+                event.snake_case_name(),
+                error
+            );
+            // Submissions are the only messages the bot acts on
+            if let serenity::FullEvent::Message { new_message } = event {
+                if let Err(e) = new_message.reply(ctx, error.user_message()).await {
+                    println!("Error while handling error: {}", e)
+                }
+            }
         }
         error => {
             if let Err(e) = poise::builtins::on_error(error).await {
@@ -101,6 +188,194 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
     }
 }
 
+/// Id of the submission for the `index`th attachment of a message,
+/// the first one keeps the message id so single attachment submissions look like they always did
+fn submission_id(message_id: MessageId, index: usize) -> String {
+    match index {
+        0 => message_id.to_string(),
+        _ => format!("{}-{}", message_id, index),
+    }
+}
+
+impl SubmissionDoc {
+    pub fn info(&self) -> SubmissionInfo {
+        SubmissionInfo {
+            caption: self.caption.clone(),
+            source: self.source.clone(),
+            credit: self.credit.clone(),
+            featured: self.featured.clone(),
+            characters: self.characters.clone(),
+        }
+    }
+
+    /// Link to the message the submission was sent in
+    fn link(&self) -> Option<String> {
+        Some(format!(
+            "https://discord.com/channels/{}/{}/{}",
+            self.guild_id.as_deref().unwrap_or("@me"),
+            self.channel_id.as_deref()?,
+            self.message_id
+        ))
+    }
+
+    /// Public message the decisions are announced as replies to, `None` when they're
+    /// announced next to the review card
+    fn acknowledgement(&self) -> Option<(ChannelId, MessageId)> {
+        Some((
+            self.channel_id.as_deref()?.parse().ok()?,
+            self.acknowledgement_id.as_deref()?.parse().ok()?,
+        ))
+    }
+
+    /// Review card of the message, `None` for submissions stored before it was recorded
+    fn review_card(&self) -> Option<(ChannelId, MessageId)> {
+        Some((
+            self.review_channel_id.as_deref()?.parse().ok()?,
+            self.review_message_id.as_deref()?.parse().ok()?,
+        ))
+    }
+
+    /// Kind, dimensions, size and status, as shown on the review card
+    fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        parts.push(
+            match self.media_type {
+                Some(MediaType::Image) => "Image",
+                Some(MediaType::Gif) => "GIF",
+                Some(MediaType::Video) => "Video",
+                Some(MediaType::Unknown) | None => "Unknown media",
+            }
+            .to_owned(),
+        );
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            parts.push(format!("{}×{}", width, height));
+        }
+        if let Some(size) = self.file_size {
+            parts.push(format!("{:.1} MB", size as f64 / 1_048_576.0));
+        }
+        parts.push(format!("**{}**", self.status));
+        parts.join(" · ")
+    }
+}
+
+/// Review card of the attachments of a message, built only from what is stored so it can be
+/// rendered again after the submission message is gone.
+/// Embeds sharing the same url are displayed by Discord as a single gallery
+fn review_card(submissions: &[SubmissionDoc]) -> Vec<CreateEmbed> {
+    let Some(first) = submissions.first() else {
+        return Vec::new();
+    };
+    let url = first.link().unwrap_or_else(|| first.image_url.clone());
+    submissions
+        .iter()
+        // Leaves room for the duplicates embed, Discord allows 10 per message
+        .take(9)
+        .enumerate()
+        .map(|(i, submission)| {
+            let embed = CreateEmbed::new().url(&url).image(&submission.image_url);
+            if i != 0 {
+                return embed;
+            }
+            let mut description = format!(
+                "Submitted by <@{}> <t:{}:R>",
+                first.discord_submitter_id, first.time_of_submission
+            );
+            if let Some(channel) = &first.channel_id {
+                description += &format!(" in <#{}>", channel);
+            }
+            let info = first.info();
+            let info_fields = [
+                ("Caption", info.caption),
+                ("Featured", info.featured),
+                ("Credit", info.credit),
+                ("Source", info.source),
+            ]
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.to_owned(), value?, true)));
+            embed
+                .title("Submission")
+                .description(description)
+                .fields(info_fields)
+                .fields(
+                    submissions
+                        .iter()
+                        .enumerate()
+                        .take(20)
+                        .map(|(i, s)| (format!("#{} `{}`", i + 1, s._id), s.summary(), false)),
+                )
+                .footer(CreateEmbedFooter::new(
+                    submissions
+                        .iter()
+                        .enumerate()
+                        .map(|(i, s)| format!("#{} {}", i + 1, s._id))
+                        .collect::<Vec<_>>()
+                        .join(" | "),
+                ))
+                .timestamp(
+                    serenity::Timestamp::from_unix_timestamp(first.time_of_submission)
+                        .unwrap_or_else(|_| serenity::Timestamp::now()),
+                )
+        })
+        .collect()
+}
+
+/// Approve, reject and add info buttons attached to every submission,
+/// with menus to review attachments one by one when a message has more than one
+fn review_components(submissions: &[SubmissionDoc], disabled: bool) -> Vec<CreateActionRow> {
+    let Some(first) = submissions.first() else {
+        return Vec::new();
+    };
+    let custom_id = |action| ReviewComponentId::new(action, &first._id).to_string();
+    let mut rows = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(custom_id(ReviewAction::Approve))
+            .label(if submissions.len() > 1 {
+                "Approve All"
+            } else {
+                "Approve Fumo"
+            })
+            .style(serenity::ButtonStyle::Primary)
+            .disabled(disabled),
+        CreateButton::new(custom_id(ReviewAction::Reject))
+            .label(if submissions.len() > 1 {
+                "Reject All"
+            } else {
+                "Reject Fumo"
+            })
+            .style(serenity::ButtonStyle::Danger)
+            .disabled(disabled),
+        CreateButton::new(custom_id(ReviewAction::AddInfo))
+            .style(serenity::ButtonStyle::Secondary)
+            .label("Add Info about the submission")
+            .disabled(disabled),
+    ])];
+    if submissions.len() > 1 {
+        let options: Vec<_> = submissions
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                CreateSelectMenuOption::new(format!("#{} {}", i + 1, s._id), s._id.clone())
+            })
+            .collect();
+        for (action, placeholder) in [
+            (ReviewAction::ApproveItems, "Approve some attachments"),
+            (ReviewAction::RejectItems, "Reject some attachments"),
+        ] {
+            rows.push(CreateActionRow::SelectMenu(
+                CreateSelectMenu::new(
+                    custom_id(action),
+                    CreateSelectMenuKind::String {
+                        options: options.clone(),
+                    },
+                )
+                .placeholder(placeholder)
+                .max_values(options.len() as u8)
+                .disabled(disabled),
+            ));
+        }
+    }
+    rows
+}
+
 async fn event_handler(
     ctx: &serenity::Context,
     event: &serenity::FullEvent,
@@ -115,210 +390,255 @@ async fn event_handler(
             if msg.author.bot {
                 return Ok(());
             }
-            if msg.channel_id == *FUMOS_CHANNEL_ID {
-                if msg.attachments.len() == 0 {
-                    msg.reply(ctx, format!("Please attach a fumo image to your message"))
+            if data.is_submission_channel(msg.guild_id, msg.channel_id) {
+                if data.is_blacklisted(msg.author.id, msg.guild_id) {
+                    msg.reply(ctx, "You are blacklisted and can't submit fumos")
+                        .await?;
+                    return Ok(());
+                }
+                if msg.attachments.is_empty() {
+                    msg.reply(ctx, "Please attach a fumo image to your message")
                         .await?;
                     return Ok(());
                 }
-                let reply_msg = CreateMessage::new()
-                    .content(format!(
-                        "<@{}> Fumo submission succesfully sent to review \n -# Only the first media attachment is going to be considered",
-                        msg.author.id.to_string()
-                    ))
-                    .button(
-                        CreateButton::new("approve")
-                            .label("Approve Fumo")
-                            .style(serenity::ButtonStyle::Primary),
-                    )
-                    .button(
-                        CreateButton::new("reject")
-                            .label("Reject Fumo")
-                            .style(serenity::ButtonStyle::Danger),
-                    )
-                    .button(
-                        CreateButton::new("add_info")
-                            .style(serenity::ButtonStyle::Secondary)
-                            .label("Add Info about the submission"),
-                    )
-                    .reference_message(msg);
-
-                let submission = SubmissionDoc {
-                    _id: msg.id.to_string(),
-                    image_url: msg.attachments[0].url.clone(),
-                    caption: None,
-                    source: None,
-                    credit: None,
-                    featured: None,
-                    approved: false,
-                    discarted: false,
-                    discord_submitter_id: msg.author.id.to_string(),
-                    time_of_submission: msg.timestamp.timestamp(),
-                };
 
-                data.submissions_collection
-                    .insert_one(submission)
-                    .await
-                    .expect("Failed to insert submission into database");
-                msg.channel_id.send_message(ctx, reply_msg).await?;
-                return Ok(());
-            }
-            if msg.content.to_lowercase() == "ping" && msg.author.id != ctx.cache.current_user().id
-            {
-                msg.reply(ctx, format!("Pong!!!")).await?;
-            }
-        }
-        serenity::FullEvent::InteractionCreate { interaction } => {
-            if interaction.as_message_component().is_some() {
-                let component = interaction.as_message_component().unwrap();
-                let mut component_copy: ComponentInteraction = component.clone();
-                let mut old_msg = component_copy.message;
-                match component.data.custom_id.as_str() {
-                    "approve" => {
-                        if !data.curators.contains(&component.user.id) {
-                            component
-                                .create_response(
-                                    ctx,
-                                    serenity::CreateInteractionResponse::Message(
-                                        CreateInteractionResponseMessage::new().content(
-                                            "You are not a curator, you can't approve fumos",
-                                        ),
-                                    ),
-                                )
-                                .await?;
-                            return Ok(());
+                // Every attachment is reviewed on its own, grouped by the message they were sent in
+                let mut submissions = Vec::new();
+                let mut refused = Vec::new();
+                for (i, attachment) in msg.attachments.iter().enumerate() {
+                    let inspected = match data.config.media.check_size(attachment.size as u64) {
+                        Ok(()) => {
+                            let (bytes, _) =
+                                storage::download_media(&data.web_client, &attachment.url).await?;
+                            data.config
+                                .media
+                                .inspect(&bytes)
+                                .map(|media| (media, bytes))
                         }
-                        let referenced = &old_msg
-                            .message_reference
-                            .as_ref()
-                            .expect("No referenced message in fumo submission reply")
-                            .message_id
-                            .unwrap();
-
-                        let channel = &component.channel_id;
-                        let referenced = channel.message(&ctx.http, referenced).await?;
-                        let submission_creator_id = referenced.author.id.to_string();
-                        println!("{}", submission_creator_id);
-                        component
-                            .create_response(
+                        Err(e) => Err(e),
+                    };
+                    let (media, bytes) = match inspected {
+                        Ok(inspected) => inspected,
+                        Err(reason) => {
+                            refused.push(format!("- `{}` {}", attachment.filename, reason));
+                            continue;
+                        }
+                    };
+                    let dhash = match media.media_type {
+                        MediaType::Image | MediaType::Gif => duplicates::hash_image(bytes).await,
+                        MediaType::Video | MediaType::Unknown => None,
+                    };
+                    submissions.push(SubmissionDoc {
+                        _id: submission_id(msg.id, i),
+                        message_id: msg.id.to_string(),
+                        guild_id: msg.guild_id.map(|g| g.to_string()),
+                        channel_id: Some(msg.channel_id.to_string()),
+                        acknowledgement_id: None,
+                        review_channel_id: None,
+                        review_message_id: None,
+                        image_url: attachment.url.clone(),
+                        caption: None,
+                        source: None,
+                        credit: None,
+                        featured: None,
+                        characters: Vec::new(),
+                        media_type: Some(media.media_type),
+                        // Videos aren't parsed, Discord knows their size
+                        width: media.width.or(attachment.width),
+                        height: media.height.or(attachment.height),
+                        file_size: Some(media.size),
+                        dhash: dhash.map(media::format_hash),
+                        status: SubmissionStatus::Pending,
+                        history: vec![StatusTransition::created(
+                            msg.author.id.to_string(),
+                            msg.timestamp.timestamp(),
+                        )],
+                        discord_submitter_id: msg.author.id.to_string(),
+                        time_of_submission: msg.timestamp.timestamp(),
+                    });
+                }
+
+                if !refused.is_empty() {
+                    msg.reply(
+                        ctx,
+                        format!(
+                            "{}:\n{}\n-# Accepted formats: {}",
+                            if submissions.is_empty() {
+                                "Your submission was not accepted"
+                            } else {
+                                "Some attachments were not accepted"
+                            },
+                            refused.join("\n"),
+                            data.config.media.allowed_mime_types.join(", ")
+                        ),
+                    )
+                    .await?;
+                }
+                if submissions.is_empty() {
+                    return Ok(());
+                }
+
+                let mut duplicate_lines = Vec::new();
+                for (i, submission) in submissions.iter().enumerate() {
+                    let Some(hash) = submission.dhash.as_deref().and_then(media::parse_hash) else {
+                        continue;
+                    };
+                    let matches =
+                        duplicates::find_duplicates(data, hash, &submission.message_id).await?;
+                    for duplicate in matches.iter().take(5) {
+                        duplicate_lines.push(format!(
+                            "#{} looks like [`{}`]({}) ({:.0}% similar)",
+                            i + 1,
+                            duplicate.id,
+                            duplicate.link,
+                            duplicate.similarity * 100.0
+                        ));
+                    }
+                }
+                let mut embeds = review_card(&submissions);
+                if let Some(first) = embeds.first_mut() {
+                    *first = first.clone().author(
+                        CreateEmbedAuthor::new(&msg.author.name).icon_url(msg.author.face()),
+                    );
+                }
+                if !duplicate_lines.is_empty() {
+                    embeds.push(
+                        CreateEmbed::new()
+                            .title("Possible duplicates")
+                            .description(duplicate_lines.join("\n"))
+                            .colour(serenity::Colour::ORANGE),
+                    );
+                }
+
+                let confirmation = format!(
+                    "<@{}> {} sent to review",
+                    msg.author.id,
+                    if submissions.len() == 1 {
+                        "Fumo submission succesfully".to_owned()
+                    } else {
+                        format!("{} fumo submissions succesfully", submissions.len())
+                    }
+                );
+                let review_channel = data
+                    .guild_settings(msg.guild_id)
+                    .and_then(|settings| settings.review_channel());
+
+                // Curators review in their own channel when the guild has one,
+                // the submitter only gets a public acknowledgement then.
+                // The card is sent before the submissions are stored, one without a card
+                // couldn't be reviewed and would be flagged as a duplicate of resubmissions
+                let (acknowledgement, review_msg) = match review_channel {
+                    Some(review_channel) => {
+                        // Submitters can't see the review channel, they edit their
+                        // submission from the acknowledgement
+                        let acknowledgement = msg
+                            .channel_id
+                            .send_message(
                                 ctx,
-                                serenity::CreateInteractionResponse::Message(
-                                    CreateInteractionResponseMessage::new().content(format!(
-                                        "<@{}> Your fumo submission has been approved 🎉. You can check it out with id `{}`",
-                                        submission_creator_id,
-                                        referenced.id.to_string()
-                                    )),
-                                ),
+                                CreateMessage::new()
+                                    .content(confirmation)
+                                    .button(
+                                        CreateButton::new(
+                                            ReviewComponentId::new(
+                                                ReviewAction::AddInfo,
+                                                &submissions[0]._id,
+                                            )
+                                            .to_string(),
+                                        )
+                                        .style(serenity::ButtonStyle::Secondary)
+                                        .label("Add Info about the submission"),
+                                    )
+                                    .reference_message(msg),
                             )
                             .await?;
-
-                        #[rustfmt::skip]
-                       // add_fumo_to_db(&data.fumos_collection, fumo_to_create).await.expect("Failed to add fumo to db");
-                        data.submissions_collection
-                            .update_one(
-                                doc! {
-                                    "_id": old_msg
-                                        .message_reference
-                                        .as_ref()
-                                        .expect("No referenced message in fumo submission reply")
-                                        .message_id.unwrap().to_string()
-                                },
-                                doc! {
-                                    "$set": {
-                                        "approved": true
-                                    }
-                                },
+                        let review_msg = review_channel
+                            .send_message(
+                                ctx,
+                                CreateMessage::new()
+                                    .content(format!(
+                                        "New submission from <@{}> in <#{}>",
+                                        msg.author.id, msg.channel_id
+                                    ))
+                                    .embeds(embeds)
+                                    .components(review_components(&submissions, false)),
                             )
-                            .await
-                            .expect("Error while giving fumo the approved flag");
-                    }
-                    "reject" => {
-                        if !data.curators.contains(&component.user.id) {
-                            component
-                                .create_response(
-                                    ctx,
-                                    serenity::CreateInteractionResponse::Message(
-                                        CreateInteractionResponseMessage::new().content(
-                                            "You are not a curator, you can't approve fumos",
-                                        ),
-                                    ),
-                                )
-                                .await?;
-                            return Ok(());
+                            .await;
+                        match review_msg {
+                            Ok(review_msg) => (Some(acknowledgement), review_msg),
+                            Err(e) => {
+                                let _ = acknowledgement.delete(ctx).await;
+                                return Err(e.into());
+                            }
                         }
-                        let referenced = &old_msg
-                            .message_reference
-                            .as_ref()
-                            .expect("No referenced message in fumo submission reply")
-                            .message_id
-                            .unwrap();
-                        let channel = &component.channel_id;
-                        let referenced = channel.message(&ctx.http, referenced).await?;
-                        let submission_creator_id = referenced.author.id.to_string();
-                        data.fumos_collection
-                            .update_one(
-                                doc! {
-                                    "_id": referenced.id.to_string()
-                                },
-                                doc! {
-                                    "$set": {
-                                        "discarted": true
-                                    }
-                                },
-                            )
-                            .await
-                            .expect("Failed to reject fumo in db (add discarted flag)");
-                        component
-                            .create_response(
+                    }
+                    None => {
+                        let review_msg = msg
+                            .channel_id
+                            .send_message(
                                 ctx,
-                                serenity::CreateInteractionResponse::Message(
-                                    CreateInteractionResponseMessage::new().content(format!(
-                                        "<@{}> Your fumo submission has been denied 😟.",
-                                        referenced.author.id.to_string()
-                                    )),
-                                ),
+                                CreateMessage::new()
+                                    .content(confirmation)
+                                    .embeds(embeds)
+                                    .components(review_components(&submissions, false))
+                                    .reference_message(msg),
                             )
                             .await?;
+                        (None, review_msg)
                     }
-                    "add_info" => {
-                        println!("{:?}", old_msg);
-
-                        let modal_response = poise::execute_modal_on_component_interaction::<
-                            MoreInfoModal,
-                        >(
-                            Box::new(ctx.clone()), component.clone(), None, None
-                        )
-                        .await?
-                        .ok_or("Couldnt parse modal successfully");
-
-                        #[rustfmt::skip]
-                        let MoreInfoModal {caption, credit, source, featured} = &modal_response.unwrap();
-                        data.submissions_collection.update_one(doc!{
-                            "_id": old_msg.message_reference.as_ref().expect("No referenced message in fumo submission reply").message_id.unwrap().to_string()
-                        }, doc!{
-                            "$set": {
-                                "caption": caption,
-                                "credit": credit,
-                                "source": source,
-                                "featured": featured
-                            }
-                        },).await.expect("Failed to update submission with more info");
-
-                        old_msg
-                            .edit(
-                                ctx,
-                                //edit message to add a embed
-                                serenity::EditMessage::new().add_embed(
-                                    serenity::CreateEmbed::new()
-                                        .description("More info succesfully added"),
-                                ),
-                            )
-                            .await
-                            .expect("Failed to edit message to add embed");
+                };
+                for submission in &mut submissions {
+                    submission.review_channel_id = Some(review_msg.channel_id.to_string());
+                    submission.review_message_id = Some(review_msg.id.to_string());
+                    submission.acknowledgement_id =
+                        acknowledgement.as_ref().map(|ack| ack.id.to_string());
+                }
+                if let Err(e) = data.submissions_collection.insert_many(&submissions).await {
+                    // Buttons on a card without submissions would only fail
+                    let _ = review_msg.delete(ctx).await;
+                    if let Some(acknowledgement) = acknowledgement {
+                        let _ = acknowledgement.delete(ctx).await;
                     }
-                    _ => {}
+                    return Err(e.into());
                 }
-            };
+                return Ok(());
+            }
+            if msg.content.to_lowercase() == "ping" && msg.author.id != ctx.cache.current_user().id
+            {
+                msg.reply(ctx, "Pong!!!").await?;
+            }
+        }
+        serenity::FullEvent::MessageDelete {
+            channel_id,
+            deleted_message_id,
+            guild_id,
+        } if data.is_submission_channel(*guild_id, *channel_id) => {
+            let submissions: Vec<SubmissionDoc> = data
+                .submissions_collection
+                .find(doc! { "message_id": deleted_message_id.to_string() })
+                .await?
+                .try_collect()
+                .await?;
+            for submission in submissions {
+                // Discord doesn't say who deleted the message, assume it was the submitter
+                match transition(
+                    &data.submissions_collection,
+                    &submission._id,
+                    SubmissionStatus::Withdrawn,
+                    &submission.discord_submitter_id,
+                    Some("Submission message was deleted".to_owned()),
+                    None,
+                )
+                .await
+                {
+                    Ok(_) | Err(TransitionError::Invalid { .. }) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        serenity::FullEvent::InteractionCreate { interaction } => {
+            if let Some(component) = interaction.as_message_component() {
+                interactions::handle_component(ctx, component, data).await;
+            }
         }
         _ => {}
     }
@@ -330,15 +650,118 @@ async fn main() {
     env_logger::init();
     dotenv().ok();
 
-    let MONGO_URI = std::env::var("MONGO_URI").expect("Expected a mongo uri in the environment");
-    let mongo = mongodb::Client::with_uri_str(MONGO_URI).await.unwrap();
-    let fumo_api_endpoint = "http://localhost:6969".to_string();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mongo = mongodb::Client::with_uri_str(&config.mongo_uri)
+        .await
+        .unwrap_or_else(|e| fatal("Invalid MONGO_URI", e));
 
     let web_client = reqwest::Client::new();
+    let fumo_api = FumoApi::new(
+        web_client.clone(),
+        &config.fumo_api_endpoint,
+        config.fumo_api_timeout,
+    );
 
-    let db = mongo.database("fumo-api");
+    let storage: Box<dyn FumoStorage> = match &config.storage {
+        StorageConfig::Local {
+            root,
+            public_base_url,
+        } => Box::new(LocalStorage::new(root.clone(), public_base_url.clone())),
+        StorageConfig::S3 {
+            endpoint,
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key,
+            public_base_url,
+        } => Box::new(
+            S3Storage::new(
+                web_client.clone(),
+                endpoint,
+                bucket.clone(),
+                region.clone(),
+                access_key_id.clone(),
+                secret_access_key.clone(),
+                public_base_url.clone(),
+            )
+            .unwrap_or_else(|e| fatal("R2_ENDPOINT is not a valid url", e)),
+        ),
+    };
+
+    let db = mongo.database(&config.database);
+    let supports_transactions = promotion::supports_transactions(&db).await;
+    if !supports_transactions {
+        println!("Mongo deployment doesn't support transactions, submissions will be promoted without one");
+    }
     let fumos_collection = db.collection("fumos");
+    if let Err(e) = search::create_text_index(&fumos_collection).await {
+        println!(
+            "Failed to create the search index, /search won't work: {}",
+            e
+        );
+    }
     let submissions_collection = db.collection("submissions");
+    let migrations_collection = db.collection("migrations");
+    let curators_collection = db.collection("curators");
+    if curators::seed_legacy(&curators_collection, &migrations_collection)
+        .await
+        .unwrap_or_else(|e| fatal("Failed to seed the curators", e))
+    {
+        println!("Added the previously hardcoded curator to the curators");
+    }
+    let curators = CuratorRoster::load(&curators_collection)
+        .await
+        .unwrap_or_else(|e| fatal("Failed to load curators", e));
+    let guild_settings_collection = db.collection("guild_settings");
+    let guilds = settings::load(&guild_settings_collection)
+        .await
+        .unwrap_or_else(|e| fatal("Failed to load guild settings", e));
+    let characters_collection = db.collection("characters");
+    let seeded = characters::seed(&characters_collection)
+        .await
+        .unwrap_or_else(|e| fatal("Failed to seed the character registry", e));
+    if seeded > 0 {
+        println!("Seeded the character registry with {} characters", seeded);
+    }
+    let characters = CharacterRegistry::load(&characters_collection)
+        .await
+        .unwrap_or_else(|e| fatal("Failed to load characters", e));
+    let blacklist_collection = db.collection("blacklist");
+    let imported = blacklist::import_legacy(
+        &blacklist_collection,
+        &migrations_collection,
+        &config.users_in_blacklist,
+    )
+    .await
+    .unwrap_or_else(|e| fatal("Failed to import USERS_IN_BLACKLIST", e));
+    if imported > 0 {
+        println!("Imported {} users from USERS_IN_BLACKLIST", imported);
+    }
+    let blacklist = blacklist::load(&blacklist_collection)
+        .await
+        .unwrap_or_else(|e| fatal("Failed to load blacklist", e));
+    let migrated = submissions::migrate_legacy_submissions(&submissions_collection)
+        .await
+        .unwrap_or_else(|e| fatal("Failed to migrate legacy submissions", e));
+    if migrated.converted > 0 {
+        println!(
+            "Migrated {} submissions to the status field",
+            migrated.converted
+        );
+    }
+    if migrated.grouped > 0 {
+        println!(
+            "Grouped {} submissions by the message they were sent in",
+            migrated.grouped
+        );
+    }
 
     // FrameworkOptions contains all of poise's configuration option in one struct
     // Every option can be omitted to use its default value
@@ -348,9 +771,20 @@ async fn main() {
             commands::hello(),
             commands::fumo(),
             commands::random(),
+            commands::fumos(),
+            commands::push(),
+            commands::backfill_hashes(),
+            curators::curators(),
+            blacklist::blacklist(),
+            settings::settings(),
+            characters::characters(),
+            search::search(),
+            gallery::gallery(),
         ],
         prefix_options: poise::PrefixFrameworkOptions {
-            prefix: Some(")".into()),
+            dynamic_prefix: Some(|ctx| {
+                Box::pin(async move { Ok(Some(ctx.data.prefix(ctx.guild_id))) })
+            }),
             edit_tracker: Some(Arc::new(poise::EditTracker::for_timespan(
                 Duration::from_secs(3600),
             ))),
@@ -374,7 +808,9 @@ async fn main() {
         // Every command invocation must pass this check to continue execution
         command_check: Some(|ctx| {
             Box::pin(async move {
-                if (*USERS_IN_BLACKLIST).contains(&ctx.author().id) {
+                // Owners are never locked out, they are the ones who can undo a blacklist
+                let is_owner = ctx.framework().options().owners.contains(&ctx.author().id);
+                if !is_owner && ctx.data().is_blacklisted(ctx.author().id, ctx.guild_id()) {
                     return Ok(false);
                 }
                 if false {
@@ -392,17 +828,28 @@ async fn main() {
         ..Default::default()
     };
 
+    let token = config.discord_token.clone();
     let framework = poise::Framework::builder()
         .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
                 println!("Logged in as {}", _ready.user.name);
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
                 Ok(Data {
+                    config,
                     fumos_collection,
                     submissions_collection,
                     web_client,
-                    fumo_api_endpoint,
-                    curators: vec![UserId::from(688476559019212805)],
+                    fumo_api,
+                    storage,
+                    supports_transactions,
+                    curators_collection,
+                    curators: RwLock::new(curators),
+                    blacklist_collection,
+                    blacklist: RwLock::new(blacklist),
+                    guild_settings_collection,
+                    guilds: RwLock::new(guilds),
+                    characters_collection,
+                    characters: RwLock::new(characters),
                 })
             })
         })
@@ -412,34 +859,18 @@ async fn main() {
     let intents =
         serenity::GatewayIntents::non_privileged() | serenity::GatewayIntents::MESSAGE_CONTENT;
 
-    let token = std::env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
-
-    let client = serenity::ClientBuilder::new(token, intents)
+    let mut client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
-        .await;
-
-    client.unwrap().start().await.unwrap()
-}
+        .await
+        .unwrap_or_else(|e| fatal("Failed to create the Discord client", e));
 
-fn upload_to_nosesisaid_cdn(image: &String) -> Result<String, Box<dyn std::error::Error>> {
-    return Ok(String::from("https://cdn.nosesisaid.com/1234.png"));
-    todo!("Upload image to nosesisaid cdn (r2 instance)");
+    if let Err(e) = client.start().await {
+        fatal("Discord client stopped", e);
+    }
 }
-async fn add_fumo_to_db(
-    fumos_collection: &MongoCollection<FumoDoc>,
-    fumo: Fumo,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let cdn_url = upload_to_nosesisaid_cdn(&fumo.image).expect("Failed to upload image to cdn");
-
-    let fumo = FumoDoc {
-        _id: fumo._id,
-        image_url: cdn_url,
-        caption: fumo.caption,
-        credit: fumo.credit,
-        source: fumo.source,
-        featured: fumo.featured,
-    };
 
-    fumos_collection.insert_one(fumo).await?;
-    Ok(())
+/// Startup failures are reported like bad settings in `Config::load`, without a panic
+fn fatal(context: &str, error: impl std::fmt::Display) -> ! {
+    eprintln!("{}: {}", context, error);
+    std::process::exit(1)
 }
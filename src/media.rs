@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+/// Same values the upload script writes to the `type` field of the fumos collection
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Image,
+    Gif,
+    Video,
+    #[serde(other)]
+    Unknown,
+}
+
+/// What a submitted file turned out to be after looking at its content
+#[derive(Debug, Clone)]
+pub struct MediaInfo {
+    pub media_type: MediaType,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub size: u64,
+}
+
+/// Which files are accepted in the submissions channel
+pub struct MediaPolicy {
+    pub allowed_mime_types: Vec<String>,
+    pub max_size: u64,
+}
+
+impl MediaPolicy {
+    /// Every type `sniff` can detect, all of them are allowed by default
+    pub const DETECTED_TYPES: &'static [&'static str] = &[
+        "image/png",
+        "image/jpeg",
+        "image/gif",
+        "image/webp",
+        "video/mp4",
+        "video/webm",
+        "video/quicktime",
+    ];
+    pub const DEFAULT_MAX_SIZE: u64 = 25 * 1024 * 1024;
+
+    /// Checks the announced size before anything is downloaded
+    pub fn check_size(&self, size: u64) -> Result<(), String> {
+        if size > self.max_size {
+            return Err(format!(
+                "is too big ({:.1} MB, the limit is {:.1} MB)",
+                size as f64 / 1_048_576.0,
+                self.max_size as f64 / 1_048_576.0
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sniffs the content of a file and checks it against the allowlist
+    pub fn inspect(&self, bytes: &[u8]) -> Result<MediaInfo, String> {
+        self.check_size(bytes.len() as u64)?;
+        let (mime_type, media_type) =
+            sniff(bytes).ok_or_else(|| "is not an image or a video".to_owned())?;
+        if !self.allowed_mime_types.iter().any(|m| m == mime_type) {
+            return Err(format!("is a `{}` file, which isn't accepted", mime_type));
+        }
+        let (width, height) = dimensions(mime_type, bytes).unzip();
+        Ok(MediaInfo {
+            media_type,
+            width,
+            height,
+            size: bytes.len() as u64,
+        })
+    }
+}
+
+/// Major brands of the `ftyp` box of MP4 videos. Other ISO media files share the box,
+/// like AVIF and HEIC images, and aren't videos
+const MP4_BRANDS: &[&[u8]] = &[
+    b"isom", b"iso2", b"iso3", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ",
+    b"M4VH", b"M4VP", b"dash", b"mmp4", b"MSNV",
+];
+
+/// Detects the format of a file from its magic bytes, the extension and
+/// content type reported by Discord are whatever the uploader's client said
+pub fn sniff(bytes: &[u8]) -> Option<(&'static str, MediaType)> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => {
+            Some(("image/png", MediaType::Image))
+        }
+        [0xFF, 0xD8, 0xFF, ..] => Some(("image/jpeg", MediaType::Image)),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(("image/gif", MediaType::Gif)),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            Some(("image/webp", MediaType::Image))
+        }
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(("video/webm", MediaType::Video)),
+        [_, _, _, _, b'f', b't', b'y', b'p', b'q', b't', b' ', b' ', ..] => {
+            Some(("video/quicktime", MediaType::Video))
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', ..]
+            if bytes
+                .get(8..12)
+                .is_some_and(|brand| MP4_BRANDS.contains(&brand)) =>
+        {
+            Some(("video/mp4", MediaType::Video))
+        }
+        _ => None,
+    }
+}
+
+/// Reads the width and height from the header of an image, videos are left to Discord
+fn dimensions(mime_type: &str, bytes: &[u8]) -> Option<(u32, u32)> {
+    let be32 = |at: usize| Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+    let le16 = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le24 = |at: usize| {
+        let b = bytes.get(at..at + 3)?;
+        Some(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+    };
+    match mime_type {
+        "image/png" => Some((be32(16)?, be32(20)?)),
+        "image/gif" => Some((le16(6)?, le16(8)?)),
+        "image/webp" => match bytes.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3FFF, le16(28)? & 0x3FFF)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(bytes.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        },
+        "image/jpeg" => jpeg_dimensions(bytes),
+        _ => None,
+    }
+}
+
+/// Walks the JPEG segments until the start of frame, which holds the dimensions
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *bytes.get(at)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(at + 1)?;
+        if marker == 0xFF {
+            // Fill byte before the actual marker
+            at += 1;
+            continue;
+        }
+        let len = u16::from_be_bytes(bytes.get(at + 2..at + 4)?.try_into().ok()?) as usize;
+        // SOF0 to SOF15, except DHT, JPG and DAC which share the range
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let height = u16::from_be_bytes(bytes.get(at + 5..at + 7)?.try_into().ok()?);
+            let width = u16::from_be_bytes(bytes.get(at + 7..at + 9)?.try_into().ok()?);
+            return Some((width as u32, height as u32));
+        }
+        at += 2 + len;
+    }
+}
+
+/// Difference hash of an image: one bit per horizontally adjacent pixel pair of the
+/// image shrunk to 9x8 in grayscale, set when the left pixel is brighter.
+/// Resizing, recompression and small edits barely change it, unlike a checksum
+pub fn dhash(bytes: &[u8]) -> Option<u64> {
+    let image = image::load_from_memory(bytes)
+        .ok()?
+        .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+        .into_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if image.get_pixel(x, y)[0] > image.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Some(hash)
+}
+
+/// Hashes are stored as hex strings, bson has no unsigned 64 bit integers
+pub fn format_hash(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+pub fn parse_hash(hash: &str) -> Option<u64> {
+    u64::from_str_radix(hash, 16).ok()
+}
+
+/// Share of identical bits between two hashes, from 0 to 1
+pub fn similarity(a: u64, b: u64) -> f64 {
+    1.0 - (a ^ b).count_ones() as f64 / 64.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0, 0x20];
+        bytes.extend_from_slice(b"ftyp");
+        bytes.extend_from_slice(brand);
+        bytes.extend_from_slice(&[0; 20]);
+        bytes
+    }
+
+    #[test]
+    fn sniffs_mp4_and_quicktime_brands() {
+        for brand in [b"isom", b"mp42", b"avc1", b"M4V "] {
+            assert_eq!(sniff(&ftyp(brand)).map(|(mime, _)| mime), Some("video/mp4"));
+        }
+        assert_eq!(
+            sniff(&ftyp(b"qt  ")).map(|(mime, _)| mime),
+            Some("video/quicktime")
+        );
+    }
+
+    #[test]
+    fn iso_media_images_are_not_videos() {
+        for brand in [b"avif", b"heic", b"mif1", b"msf1"] {
+            assert!(sniff(&ftyp(brand)).is_none());
+        }
+    }
+}
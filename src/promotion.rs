@@ -0,0 +1,208 @@
+use crate::{
+    commands::Fumo,
+    media,
+    storage::{self, FumoStorage},
+    submissions::{transition, SubmissionStatus, TransitionError},
+    Data, Error, FumoDoc, SubmissionDoc,
+};
+use mongodb::{bson::doc, error::TRANSIENT_TRANSACTION_ERROR, ClientSession, Database};
+
+pub enum PromotionOutcome {
+    Inserted,
+    /// A fumo with the same id was already in the fumos collection
+    AlreadyPresent,
+}
+
+/// Multi-document transactions are only available on replica sets and sharded clusters
+pub async fn supports_transactions(db: &Database) -> bool {
+    match db.run_command(doc! { "hello": 1 }).await {
+        Ok(hello) => hello.contains_key("setName") || hello.get_str("msg") == Ok("isdbgrid"),
+        Err(e) => {
+            println!(
+                "Failed to detect mongo topology, assuming standalone: {}",
+                e
+            );
+            false
+        }
+    }
+}
+
+/// Downloads the submitted media and rehosts it on the nosesisaid cdn (r2 instance)
+/// returning the storage key and the public url of the media
+async fn upload_to_nosesisaid_cdn(
+    web_client: &reqwest::Client,
+    storage: &dyn FumoStorage,
+    id: &str,
+    image: &str,
+) -> Result<(String, String), Error> {
+    let (bytes, content_type) = storage::download_media(web_client, image).await?;
+    // What the file really is wins over what the server it came from claims
+    let content_type = media::sniff(&bytes)
+        .map(|(mime_type, _)| mime_type.to_owned())
+        .or(content_type);
+    let key = format!(
+        "{}.{}",
+        id,
+        storage::media_extension(image, content_type.as_deref())
+    );
+    if storage.exists(&key).await? {
+        return Ok((key.clone(), storage.public_url(&key)));
+    }
+    let content_type = content_type.unwrap_or_else(|| "application/octet-stream".to_owned());
+    let url = storage.put(&key, bytes, &content_type).await?;
+    Ok((key, url))
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        *error.kind,
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(ref e)) if e.code == 11000
+    )
+}
+
+/// Rehosts the media of an approved submission and moves it to the fumos collection.
+/// The insert and the status change of the submission happen atomically when the server
+/// supports transactions, otherwise a crash in between leaves an approved submission whose
+/// fumo already exists, which the next push detects and marks as promoted
+pub async fn promote_submission(
+    data: &Data,
+    submission: SubmissionDoc,
+    by: &str,
+) -> Result<PromotionOutcome, Error> {
+    let submission_id = submission._id.clone();
+    if data
+        .fumos_collection
+        .find_one(doc! { "_id": &submission_id })
+        .await?
+        .is_some()
+    {
+        mark_promoted(data, &submission_id, by, None).await?;
+        return Ok(PromotionOutcome::AlreadyPresent);
+    }
+
+    let SubmissionDoc {
+        characters,
+        media_type,
+        width,
+        height,
+        file_size,
+        dhash,
+        ..
+    } = submission;
+    let fumo = Fumo {
+        _id: submission._id,
+        caption: submission.caption,
+        image: submission.image_url,
+        source: submission.source,
+        credit: submission.credit,
+        featured: submission.featured,
+    };
+    let (key, cdn_url) = upload_to_nosesisaid_cdn(
+        &data.web_client,
+        data.storage.as_ref(),
+        &fumo._id,
+        &fumo.image,
+    )
+    .await?;
+
+    let fumo = FumoDoc {
+        _id: fumo._id,
+        image_url: cdn_url,
+        caption: fumo.caption,
+        credit: fumo.credit,
+        source: fumo.source,
+        featured: fumo.featured,
+        characters,
+        media_type,
+        width,
+        height,
+        file_size,
+        dhash,
+    };
+
+    if data.supports_transactions {
+        return match insert_and_promote_in_transaction(data, &fumo, by).await {
+            Ok(()) => Ok(PromotionOutcome::Inserted),
+            // Someone else inserted it in the meantime, their document points to the same media
+            Err(TransitionError::Database(e)) if is_duplicate_key(&e) => {
+                mark_promoted(data, &submission_id, by, None).await?;
+                Ok(PromotionOutcome::AlreadyPresent)
+            }
+            Err(e) => {
+                // The transaction was rolled back, don't leave orphaned media in the cdn
+                data.storage.delete(&key).await?;
+                Err(e.into())
+            }
+        };
+    }
+
+    match data.fumos_collection.insert_one(&fumo).await {
+        Ok(_) => {}
+        Err(e) if is_duplicate_key(&e) => {
+            mark_promoted(data, &submission_id, by, None).await?;
+            return Ok(PromotionOutcome::AlreadyPresent);
+        }
+        Err(e) => {
+            data.storage.delete(&key).await?;
+            return Err(e.into());
+        }
+    }
+    // The fumo is live from here so its media has to stay, even if the status change fails.
+    // The next push finds the fumo and marks the submission as promoted
+    mark_promoted(data, &submission_id, by, None).await?;
+    Ok(PromotionOutcome::Inserted)
+}
+
+async fn mark_promoted(
+    data: &Data,
+    id: &str,
+    by: &str,
+    session: Option<&mut ClientSession>,
+) -> Result<(), TransitionError> {
+    transition(
+        &data.submissions_collection,
+        id,
+        SubmissionStatus::Promoted,
+        by,
+        None,
+        session,
+    )
+    .await
+    .map(|_| ())
+}
+
+async fn insert_and_promote_in_transaction(
+    data: &Data,
+    fumo: &FumoDoc,
+    by: &str,
+) -> Result<(), TransitionError> {
+    let mut session = data.fumos_collection.client().start_session().await?;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        session.start_transaction().await?;
+        let result = async {
+            data.fumos_collection
+                .insert_one(fumo)
+                .session(&mut session)
+                .await?;
+            mark_promoted(data, &fumo._id, by, Some(&mut session)).await?;
+            Ok(session.commit_transaction().await?)
+        }
+        .await;
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                // Aborting a transaction that already failed on the server can error too
+                let _ = session.abort_transaction().await;
+                let transient = matches!(
+                    &e,
+                    TransitionError::Database(e) if e.contains_label(TRANSIENT_TRANSACTION_ERROR)
+                );
+                if attempts >= 3 || !transient {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
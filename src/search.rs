@@ -0,0 +1,214 @@
+//! `/search` over the text index of the fumos collection, the results are browsed with
+//! the buttons of the message, see `SearchComponentId`
+use crate::{
+    commands::{generate_fumo_embed, Fumo},
+    custom_ids::{SearchAction, SearchComponentId},
+    Context, Data, Error, FumoDoc,
+};
+use ::serenity::all::{
+    ComponentInteraction, ComponentInteractionDataKind, CreateActionRow, CreateButton, CreateEmbed,
+    CreateEmbedFooter, CreateInteractionResponse, CreateInteractionResponseMessage,
+    CreateSelectMenu, CreateSelectMenuKind, CreateSelectMenuOption,
+};
+use mongodb::{
+    bson::{doc, Document},
+    options::IndexOptions,
+    Collection, IndexModel,
+};
+use poise::{serenity_prelude as serenity, CreateReply};
+use serenity::futures::TryStreamExt;
+
+const RESULTS_PER_PAGE: u64 = 5;
+/// The query is kept in the custom ids of the buttons, which Discord limits to 100 characters
+pub const MAX_QUERY_LENGTH: usize = 70;
+
+/// Text index searched by `/search`, a collection can only have one
+pub async fn create_text_index(fumos: &Collection<FumoDoc>) -> Result<(), Error> {
+    fumos
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! {
+                    "caption": "text",
+                    "credit": "text",
+                    "source": "text",
+                    "featured": "text",
+                })
+                .options(
+                    IndexOptions::builder()
+                        .name("fumo_search".to_owned())
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Search the fumos by caption, credit, source or featured character
+#[poise::command(prefix_command, slash_command)]
+pub async fn search(
+    ctx: Context<'_>,
+    #[description = "Words to look for"]
+    #[max_length = 70]
+    query: String,
+) -> Result<(), Error> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(Error::Validation("Tell me what to look for".to_owned()));
+    }
+    if query.chars().count() > MAX_QUERY_LENGTH {
+        return Err(Error::Validation(format!(
+            "Searches can't be longer than {} characters",
+            MAX_QUERY_LENGTH
+        )));
+    }
+    let (embed, components) = results_page(ctx.data(), query, 1).await?;
+    ctx.send(CreateReply::default().embed(embed).components(components))
+        .await?;
+    Ok(())
+}
+
+/// Results of the page, clamped to the existing ones, and the components to browse them
+async fn results_page(
+    data: &Data,
+    query: &str,
+    page: u64,
+) -> Result<(CreateEmbed, Vec<CreateActionRow>), Error> {
+    let fumos = data.fumos_collection.clone_with_type::<Document>();
+    let filter = doc! { "$text": { "$search": query } };
+    let total = fumos.count_documents(filter.clone()).await?;
+    if total == 0 {
+        return Err(Error::NotFound(format!("No fumo matches `{}`", query)));
+    }
+    let pages = total.div_ceil(RESULTS_PER_PAGE);
+    let page = page.clamp(1, pages);
+    let score = doc! { "$meta": "textScore" };
+    let results: Vec<Document> = fumos
+        .find(filter)
+        .projection(doc! {
+            "caption": 1,
+            "credit": 1,
+            "featured": 1,
+            "score": score.clone(),
+        })
+        .sort(doc! { "score": score, "_id": -1 })
+        .skip((page - 1) * RESULTS_PER_PAGE)
+        .limit(RESULTS_PER_PAGE as i64)
+        .await?
+        .try_collect()
+        .await?;
+
+    let first = (page - 1) * RESULTS_PER_PAGE + 1;
+    let mut lines = Vec::new();
+    let mut options = Vec::new();
+    for (n, fumo) in (first..).zip(&results) {
+        let Ok(id) = fumo.get_str("_id") else {
+            continue;
+        };
+        let caption = fumo.get_str("caption").unwrap_or("No caption");
+        let details: Vec<_> = [("Featured", "featured"), ("By", "credit")]
+            .into_iter()
+            .filter_map(|(label, field)| {
+                let value = fumo.get_str(field).ok()?;
+                Some(format!("{}: {}", label, value))
+            })
+            .collect();
+        let mut line = format!("**{}.** `{}` {}", n, id, caption);
+        if !details.is_empty() {
+            line += &format!("\n{}", details.join(" · "));
+        }
+        lines.push(line);
+        // Option labels and descriptions are limited to 100 characters
+        options.push(
+            CreateSelectMenuOption::new(format!("#{} {}", n, id), id)
+                .description(caption.chars().take(100).collect::<String>()),
+        );
+    }
+
+    let embed = CreateEmbed::new()
+        .title(format!("Search: {}", query))
+        .description(lines.join("\n"))
+        .footer(CreateEmbedFooter::new(format!(
+            "Page {}/{} · {} results",
+            page, pages, total
+        )));
+    let custom_id = |action| SearchComponentId::new(action, page, query).to_string();
+    let mut components = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(custom_id(SearchAction::Previous))
+            .label("Previous")
+            .style(serenity::ButtonStyle::Secondary)
+            .disabled(page == 1),
+        CreateButton::new(custom_id(SearchAction::Next))
+            .label("Next")
+            .style(serenity::ButtonStyle::Secondary)
+            .disabled(page == pages),
+    ])];
+    if !options.is_empty() {
+        components.push(CreateActionRow::SelectMenu(
+            CreateSelectMenu::new(
+                custom_id(SearchAction::Open),
+                CreateSelectMenuKind::String { options },
+            )
+            .placeholder("Open a result"),
+        ));
+    }
+    Ok((embed, components))
+}
+
+/// Called by the router for the components of the results, anyone can use them
+pub async fn handle_component(
+    ctx: &serenity::Context,
+    component: &ComponentInteraction,
+    data: &Data,
+    component_id: SearchComponentId,
+) -> Result<(), Error> {
+    let page = match component_id.action {
+        SearchAction::Previous => component_id.page.saturating_sub(1),
+        SearchAction::Next => component_id.page + 1,
+        SearchAction::Open => return open(ctx, component, data).await,
+    };
+    let (embed, components) = results_page(data, &component_id.query, page).await?;
+    component
+        .create_response(
+            ctx,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .embed(embed)
+                    .components(components),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Shows the picked result like `/fumo` does, only to whoever picked it
+async fn open(
+    ctx: &serenity::Context,
+    component: &ComponentInteraction,
+    data: &Data,
+) -> Result<(), Error> {
+    let ComponentInteractionDataKind::StringSelect { values } = &component.data.kind else {
+        return Ok(());
+    };
+    let Some(id) = values.first() else {
+        return Ok(());
+    };
+    let fumo = data
+        .fumos_collection
+        .clone_with_type::<Document>()
+        .find_one(doc! { "_id": id })
+        .await?
+        .and_then(|fumo| Fumo::from_document(&fumo))
+        .ok_or_else(|| Error::NotFound(format!("Fumo {} doesn't exist anymore", id)))?;
+    component
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .embed(generate_fumo_embed(fumo))
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,377 @@
+use crate::{Context, Data, Error};
+use mongodb::{
+    bson::{doc, Bson, Document},
+    options::ReturnDocument,
+    Collection,
+};
+use poise::{serenity_prelude as serenity, CreateReply};
+use serde::{Deserialize, Serialize};
+use serenity::{futures::TryStreamExt, ChannelId, GuildId, RoleId};
+use std::collections::HashMap;
+
+/// Which blacklist entries apply in a guild
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, poise::ChoiceParameter,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BlacklistScope {
+    /// Everyone blacklisted anywhere
+    #[default]
+    #[name = "Everywhere"]
+    Global,
+    /// Only users blacklisted from this guild
+    #[name = "Only this server"]
+    Server,
+}
+
+impl From<BlacklistScope> for Bson {
+    fn from(scope: BlacklistScope) -> Self {
+        Bson::String(
+            match scope {
+                BlacklistScope::Global => "global",
+                BlacklistScope::Server => "server",
+            }
+            .to_owned(),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GuildSettings {
+    /// Discord guild id
+    _id: String,
+    /// Channels where fumos can be submitted
+    #[serde(default)]
+    submission_channels: Vec<String>,
+    /// Channel the review cards are sent to instead of replying to the submission
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    review_channel: Option<String>,
+    /// Role whose members are curators in this guild
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    curator_role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+    #[serde(default)]
+    pub blacklist_scope: BlacklistScope,
+}
+
+impl GuildSettings {
+    pub fn is_submission_channel(&self, channel: ChannelId) -> bool {
+        self.submission_channels
+            .iter()
+            .any(|c| *c == channel.to_string())
+    }
+
+    pub fn review_channel(&self) -> Option<ChannelId> {
+        self.review_channel.as_deref()?.parse().ok()
+    }
+
+    pub fn curator_role(&self) -> Option<RoleId> {
+        self.curator_role.as_deref()?.parse().ok()
+    }
+}
+
+pub async fn load(
+    settings: &Collection<GuildSettings>,
+) -> Result<HashMap<GuildId, GuildSettings>, Error> {
+    let mut guilds = HashMap::new();
+    let mut cursor = settings.find(doc! {}).await?;
+    while let Some(guild) = cursor.try_next().await? {
+        guilds.insert(guild._id.parse()?, guild);
+    }
+    Ok(guilds)
+}
+
+impl Data {
+    /// Settings of a guild, `None` when it never changed them or outside of guilds
+    pub fn guild_settings(&self, guild: Option<GuildId>) -> Option<GuildSettings> {
+        self.guilds.read().unwrap().get(&guild?).cloned()
+    }
+
+    /// Whether fumos submitted in a channel should be reviewed
+    pub fn is_submission_channel(&self, guild: Option<GuildId>, channel: ChannelId) -> bool {
+        self.config.fumos_channel_id == Some(channel)
+            || self
+                .guild_settings(guild)
+                .is_some_and(|settings| settings.is_submission_channel(channel))
+    }
+
+    /// Prefix for commands sent in a guild
+    pub fn prefix(&self, guild: Option<GuildId>) -> String {
+        self.guild_settings(guild)
+            .and_then(|settings| settings.prefix)
+            .unwrap_or_else(|| self.config.prefix.clone())
+    }
+}
+
+/// Applies `update` to the settings of the guild the command was used in and caches the result
+async fn update_settings(ctx: Context<'_>, update: Document) -> Result<GuildSettings, Error> {
+    let guild_id = ctx
+        .guild_id()
+        .ok_or_else(|| Error::Validation("This only works in a server".to_owned()))?;
+    let data = ctx.data();
+    let settings = data
+        .guild_settings_collection
+        .find_one_and_update(doc! { "_id": guild_id.to_string() }, update)
+        .upsert(true)
+        .return_document(ReturnDocument::After)
+        .await?
+        .ok_or_else(|| Error::NotFound("Guild settings were not upserted".to_owned()))?;
+    data.guilds
+        .write()
+        .unwrap()
+        .insert(guild_id, settings.clone());
+    Ok(settings)
+}
+
+/// Configure the bot in this server
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    // Only hides the group, every subcommand checks its own permissions
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands(
+        "show",
+        "add_channel",
+        "remove_channel",
+        "review_channel",
+        "curator_role",
+        "prefix",
+        "blacklist_scope"
+    ),
+    subcommand_required
+)]
+pub async fn settings(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Show the settings of this server
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn show(ctx: Context<'_>) -> Result<(), Error> {
+    let data = ctx.data();
+    let settings = data.guild_settings(ctx.guild_id());
+    let channels: Vec<String> = data
+        .config
+        .fumos_channel_id
+        .map(|c| c.to_string())
+        .into_iter()
+        .chain(
+            settings
+                .iter()
+                .flat_map(|s| s.submission_channels.iter().cloned()),
+        )
+        .map(|c| format!("<#{}>", c))
+        .collect();
+    let or_default =
+        |value: Option<String>, default: &str| value.unwrap_or_else(|| default.to_owned());
+    let settings = settings.as_ref();
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                serenity::CreateEmbed::new()
+                    .title("Settings")
+                    .field(
+                        "Submission channels",
+                        or_default((!channels.is_empty()).then(|| channels.join(", ")), "None"),
+                        false,
+                    )
+                    .field(
+                        "Review channel",
+                        or_default(
+                            settings
+                                .and_then(|s| s.review_channel())
+                                .map(|c| format!("<#{}>", c)),
+                            "Replies to the submission",
+                        ),
+                        false,
+                    )
+                    .field(
+                        "Curator role",
+                        or_default(
+                            settings
+                                .and_then(|s| s.curator_role())
+                                .map(|r| format!("<@&{}>", r)),
+                            "None",
+                        ),
+                        false,
+                    )
+                    .field(
+                        "Prefix",
+                        format!("`{}`", data.prefix(ctx.guild_id())),
+                        false,
+                    )
+                    .field(
+                        "Blacklist",
+                        poise::ChoiceParameter::name(
+                            &settings.map(|s| s.blacklist_scope).unwrap_or_default(),
+                        ),
+                        false,
+                    ),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Accept fumo submissions in a channel
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn add_channel(
+    ctx: Context<'_>,
+    #[description = "Channel where fumos can be submitted"]
+    #[channel_types("Text")]
+    channel: serenity::GuildChannel,
+) -> Result<(), Error> {
+    update_settings(
+        ctx,
+        doc! { "$addToSet": { "submission_channels": channel.id.to_string() } },
+    )
+    .await?;
+    ctx.reply(format!("Fumos sent to <#{}> will be reviewed", channel.id))
+        .await?;
+    Ok(())
+}
+
+/// Stop accepting fumo submissions in a channel
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn remove_channel(
+    ctx: Context<'_>,
+    #[description = "Channel where fumos won't be accepted anymore"]
+    #[channel_types("Text")]
+    channel: serenity::GuildChannel,
+) -> Result<(), Error> {
+    update_settings(
+        ctx,
+        doc! { "$pull": { "submission_channels": channel.id.to_string() } },
+    )
+    .await?;
+    ctx.reply(format!(
+        "<#{}> is no longer a submission channel",
+        channel.id
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Send submissions to a channel only curators can see
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn review_channel(
+    ctx: Context<'_>,
+    #[description = "Review channel, leave empty to review submissions where they are sent"]
+    #[channel_types("Text")]
+    channel: Option<serenity::GuildChannel>,
+) -> Result<(), Error> {
+    match channel {
+        Some(channel) => {
+            update_settings(
+                ctx,
+                doc! { "$set": { "review_channel": channel.id.to_string() } },
+            )
+            .await?;
+            ctx.reply(format!("Submissions will be reviewed in <#{}>", channel.id))
+                .await?;
+        }
+        None => {
+            update_settings(ctx, doc! { "$unset": { "review_channel": "" } }).await?;
+            ctx.reply("Submissions will be reviewed where they are sent")
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Make every member with a role a curator in this server
+///
+/// Curators review for every server and push to the shared fumos collection,
+/// so only the bot owners can hand that out
+#[poise::command(prefix_command, slash_command, guild_only, owners_only)]
+pub async fn curator_role(
+    ctx: Context<'_>,
+    #[description = "Curator role, leave empty to stop using one"] role: Option<serenity::Role>,
+) -> Result<(), Error> {
+    match role {
+        Some(role) => {
+            update_settings(
+                ctx,
+                doc! { "$set": { "curator_role": role.id.to_string() } },
+            )
+            .await?;
+            ctx.reply(format!("Members with <@&{}> are now curators", role.id))
+                .await?;
+        }
+        None => {
+            update_settings(ctx, doc! { "$unset": { "curator_role": "" } }).await?;
+            ctx.reply("This server no longer has a curator role")
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Change the prefix of text commands in this server
+#[poise::command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn prefix(
+    ctx: Context<'_>,
+    #[description = "New prefix, leave empty to use the default one"]
+    #[max_length = 10]
+    prefix: Option<String>,
+) -> Result<(), Error> {
+    match prefix.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+        Some(prefix) => {
+            update_settings(ctx, doc! { "$set": { "prefix": prefix } }).await?;
+        }
+        None => {
+            update_settings(ctx, doc! { "$unset": { "prefix": "" } }).await?;
+        }
+    }
+    ctx.reply(format!(
+        "Text commands now start with `{}`",
+        ctx.data().prefix(ctx.guild_id())
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Choose whether people blacklisted in other servers can use the bot here
+///
+/// Other blacklists include the global bans and the shared review queue gets every
+/// submission, so only the bot owners can lift them here
+#[poise::command(prefix_command, slash_command, guild_only, owners_only)]
+pub async fn blacklist_scope(
+    ctx: Context<'_>,
+    #[description = "Which blacklisted users are blocked in this server"] scope: BlacklistScope,
+) -> Result<(), Error> {
+    update_settings(ctx, doc! { "$set": { "blacklist_scope": scope } }).await?;
+    ctx.reply(match scope {
+        BlacklistScope::Global => "Users blacklisted in any server are blocked here",
+        BlacklistScope::Server => "Only users blacklisted from this server are blocked here",
+    })
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,487 @@
+use crate::SubmissionDoc;
+use mongodb::{
+    bson::{doc, Bson, Document},
+    options::ReturnDocument,
+    ClientSession, Collection,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionStatus {
+    Pending,
+    Approved,
+    Rejected,
+    /// Moved to the fumos collection by `push`
+    Promoted,
+    /// The submitter deleted their message
+    Withdrawn,
+}
+
+impl SubmissionStatus {
+    /// Statuses a submission can move to from `self`, the only place transitions are decided
+    pub fn next_statuses(self) -> &'static [SubmissionStatus] {
+        use SubmissionStatus::*;
+        match self {
+            Pending => &[Approved, Rejected, Withdrawn],
+            Approved => &[Rejected, Promoted, Withdrawn],
+            Rejected => &[Approved, Withdrawn],
+            Promoted | Withdrawn => &[],
+        }
+    }
+
+    /// Statuses a submission can be in to move to `self`
+    fn previous_statuses(self) -> Vec<SubmissionStatus> {
+        [
+            Self::Pending,
+            Self::Approved,
+            Self::Rejected,
+            Self::Promoted,
+            Self::Withdrawn,
+        ]
+        .into_iter()
+        .filter(|from| from.next_statuses().contains(&self))
+        .collect()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+            Self::Promoted => "promoted",
+            Self::Withdrawn => "withdrawn",
+        }
+    }
+}
+
+impl fmt::Display for SubmissionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<SubmissionStatus> for Bson {
+    fn from(status: SubmissionStatus) -> Self {
+        Bson::String(status.as_str().to_owned())
+    }
+}
+
+/// Entry of the append only audit trail of a submission, either a status change
+/// or an edit of its info, which keeps the status and lists the `changes`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatusTransition {
+    /// `None` for the entry created alongside the submission
+    pub from: Option<SubmissionStatus>,
+    pub to: SubmissionStatus,
+    /// Discord id of whoever made the change
+    pub by: String,
+    pub at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<FieldChange>,
+}
+
+impl StatusTransition {
+    pub fn created(by: String, at: i64) -> Self {
+        Self {
+            from: None,
+            to: SubmissionStatus::Pending,
+            by,
+            at,
+            reason: None,
+            changes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// What the submitter or a curator can tell about a submission
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubmissionInfo {
+    pub caption: Option<String>,
+    pub source: Option<String>,
+    pub credit: Option<String>,
+    /// Plushies featured in the image, the canonical names once resolved
+    /// with `Data::resolve_featured`
+    pub featured: Option<String>,
+    /// Ids of the registered characters in `featured`, see `characters::CharacterRegistry`
+    pub characters: Vec<String>,
+}
+
+impl SubmissionInfo {
+    /// Blank values are stored as missing
+    pub fn normalized(self) -> Self {
+        let clean =
+            |value: Option<String>| value.map(|v| v.trim().to_owned()).filter(|v| !v.is_empty());
+        Self {
+            caption: clean(self.caption),
+            source: clean(self.source),
+            credit: clean(self.credit),
+            featured: clean(self.featured),
+            characters: self.characters,
+        }
+    }
+
+    fn fields(&self) -> [(&'static str, &Option<String>); 4] {
+        [
+            ("caption", &self.caption),
+            ("source", &self.source),
+            ("credit", &self.credit),
+            ("featured", &self.featured),
+        ]
+    }
+}
+
+#[derive(Debug)]
+pub enum TransitionError {
+    NotFound,
+    Invalid {
+        from: SubmissionStatus,
+        to: SubmissionStatus,
+    },
+    /// Promoted and withdrawn submissions can't be edited anymore,
+    /// reviewed ones only by curators
+    Locked(SubmissionStatus),
+    Database(mongodb::error::Error),
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("Submission not found"),
+            Self::Invalid { from, to } => {
+                write!(f, "This submission is {} and can't be {}", from, to)
+            }
+            Self::Locked(status) => write!(f, "This submission is {} and can't be edited", status),
+            Self::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+impl From<mongodb::error::Error> for TransitionError {
+    fn from(e: mongodb::error::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+/// Moves a submission to `to`, appending the change to its history.
+/// The check and the update are a single atomic operation so concurrent clicks can't race
+pub async fn transition(
+    submissions: &Collection<SubmissionDoc>,
+    id: &str,
+    to: SubmissionStatus,
+    by: &str,
+    reason: Option<String>,
+    session: Option<&mut ClientSession>,
+) -> Result<SubmissionDoc, TransitionError> {
+    let from = to.previous_statuses();
+    // Pipeline updates evaluate `$status` against the document before the update, which
+    // records the previous status. User provided values are wrapped so they aren't parsed
+    let mut entry = doc! {
+        "from": "$status",
+        "to": { "$literal": to },
+        "by": { "$literal": by },
+        "at": { "$literal": chrono::Utc::now().timestamp() },
+    };
+    if let Some(reason) = reason {
+        entry.insert("reason", doc! { "$literal": reason });
+    }
+    let update = vec![doc! {
+        "$set": {
+            "status": { "$literal": to },
+            "history": { "$concatArrays": [{ "$ifNull": ["$history", []] }, [entry]] },
+        }
+    }];
+
+    let action = submissions
+        .find_one_and_update(doc! { "_id": id, "status": { "$in": from } }, update)
+        .return_document(ReturnDocument::After);
+    let updated = match session {
+        Some(session) => action.session(session).await?,
+        None => action.await?,
+    };
+    match updated {
+        Some(submission) => Ok(submission),
+        None => match submissions.find_one(doc! { "_id": id }).await? {
+            Some(submission) => Err(TransitionError::Invalid {
+                from: submission.status,
+                to,
+            }),
+            None => Err(TransitionError::NotFound),
+        },
+    }
+}
+
+/// Replaces the info of a submission, recording what changed in its history.
+/// Only curators can edit a submission once it left `Pending`.
+/// Returns `None` when nothing changed
+pub async fn edit_info(
+    submissions: &Collection<SubmissionDoc>,
+    id: &str,
+    info: &SubmissionInfo,
+    by: &str,
+    by_curator: bool,
+) -> Result<Option<SubmissionDoc>, TransitionError> {
+    // The update only applies to the status that was read, so an edit is never recorded
+    // against a status the submission already left. It is tried again if that happens
+    loop {
+        let submission = submissions
+            .find_one(doc! { "_id": id })
+            .await?
+            .ok_or(TransitionError::NotFound)?;
+        if submission.status.next_statuses().is_empty()
+            || (!by_curator && submission.status != SubmissionStatus::Pending)
+        {
+            return Err(TransitionError::Locked(submission.status));
+        }
+        let current = submission.info();
+        let mut set = doc! {};
+        let mut changes = Vec::new();
+        for ((field, from), (_, to)) in current.fields().into_iter().zip(info.fields()) {
+            if from != to {
+                set.insert(field, to.clone());
+                if field == "featured" {
+                    set.insert("characters", &info.characters);
+                }
+                changes.push(doc! { "field": field, "from": from.clone(), "to": to.clone() });
+            }
+        }
+        if changes.is_empty() {
+            return Ok(None);
+        }
+        let entry = doc! {
+            "from": submission.status,
+            "to": submission.status,
+            "by": by,
+            "at": chrono::Utc::now().timestamp(),
+            "changes": changes,
+        };
+
+        let updated = submissions
+            .find_one_and_update(
+                doc! { "_id": id, "status": submission.status },
+                doc! { "$set": set, "$push": { "history": entry } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        if let Some(updated) = updated {
+            return Ok(Some(updated));
+        }
+    }
+}
+
+/// What `migrate_legacy_submissions` changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LegacyMigration {
+    /// Submissions converted from the old flags to the status field
+    pub converted: u64,
+    /// Submissions given their message id, from before multi attachment support
+    pub grouped: u64,
+}
+
+/// Converts submissions using the old `approved`/`discarted` flags to the status field,
+/// seeding their history from what the flags and rejection fields recorded,
+/// and groups submissions stored before multi attachment support by their message
+pub async fn migrate_legacy_submissions(
+    submissions: &Collection<SubmissionDoc>,
+) -> Result<LegacyMigration, mongodb::error::Error> {
+    let converted = submissions
+        .update_many(
+            doc! { "status": { "$exists": false } },
+            legacy_status_pipeline(),
+        )
+        .await?
+        .modified_count;
+
+    // Submissions from before attachments were split have their message id as `_id`
+    let grouped = submissions
+        .update_many(
+            doc! { "message_id": { "$exists": false } },
+            vec![doc! { "$set": { "message_id": "$_id" } }],
+        )
+        .await?
+        .modified_count;
+    Ok(LegacyMigration { converted, grouped })
+}
+
+/// Update pipeline replacing the old flags of a submission with its status and history
+fn legacy_status_pipeline() -> Vec<Document> {
+    vec![
+        doc! {
+            "$set": {
+                "status": {
+                    "$switch": {
+                        "branches": [
+                            // Flagged by push on deployments without transactions
+                            { "case": { "$eq": ["$promoted", true] }, "then": SubmissionStatus::Promoted },
+                            { "case": { "$eq": ["$discarted", true] }, "then": SubmissionStatus::Rejected },
+                            { "case": { "$eq": ["$approved", true] }, "then": SubmissionStatus::Approved },
+                        ],
+                        "default": SubmissionStatus::Pending,
+                    }
+                },
+            }
+        },
+        doc! {
+            "$set": {
+                "history": {
+                    "$concatArrays": [
+                        [{
+                            "from": Bson::Null,
+                            "to": SubmissionStatus::Pending,
+                            "by": "$discord_submitter_id",
+                            "at": "$time_of_submission",
+                        }],
+                        {
+                            "$cond": [
+                                { "$eq": ["$status", SubmissionStatus::Pending] },
+                                [],
+                                [{
+                                    "from": SubmissionStatus::Pending,
+                                    "to": "$status",
+                                    "by": { "$ifNull": ["$rejected_by", "migration"] },
+                                    "at": { "$ifNull": ["$rejected_at", "$time_of_submission"] },
+                                    "reason": { "$ifNull": ["$rejection_reason", "$$REMOVE"] },
+                                }],
+                            ]
+                        },
+                    ]
+                },
+            }
+        },
+        doc! {
+            "$unset": ["approved", "discarted", "promoted", "rejected_by", "rejected_at", "rejection_reason"]
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [SubmissionStatus; 5] = [
+        SubmissionStatus::Pending,
+        SubmissionStatus::Approved,
+        SubmissionStatus::Rejected,
+        SubmissionStatus::Promoted,
+        SubmissionStatus::Withdrawn,
+    ];
+
+    #[test]
+    fn previous_statuses_mirror_next_statuses() {
+        for from in ALL {
+            for to in ALL {
+                assert_eq!(
+                    from.next_statuses().contains(&to),
+                    to.previous_statuses().contains(&from),
+                    "{} -> {}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transitions() {
+        use SubmissionStatus::*;
+        assert!(Pending.previous_statuses().is_empty());
+        assert!(Promoted.next_statuses().is_empty());
+        assert!(Withdrawn.next_statuses().is_empty());
+        assert_eq!(Promoted.previous_statuses(), [Approved]);
+        // Curators can change their mind until the submission is pushed
+        assert!(Approved.next_statuses().contains(&Rejected));
+        assert!(Rejected.next_statuses().contains(&Approved));
+        for status in [Pending, Approved, Rejected] {
+            assert!(status.next_statuses().contains(&Withdrawn), "{}", status);
+        }
+    }
+
+    #[test]
+    fn statuses_are_stored_as_their_name() {
+        for status in ALL {
+            assert_eq!(
+                mongodb::bson::to_bson(&status).unwrap(),
+                Bson::String(status.as_str().to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_flags_pick_the_latest_status() {
+        let pipeline = legacy_status_pipeline();
+        let switch = pipeline[0]
+            .get_document("$set")
+            .and_then(|set| set.get_document("status"))
+            .and_then(|status| status.get_document("$switch"))
+            .unwrap();
+        let branches: Vec<(String, String)> = switch
+            .get_array("branches")
+            .unwrap()
+            .iter()
+            .map(|branch| {
+                let branch = branch.as_document().unwrap();
+                let flag = branch
+                    .get_document("case")
+                    .unwrap()
+                    .get_array("$eq")
+                    .unwrap()[0]
+                    .as_str()
+                    .unwrap()
+                    .to_owned();
+                (flag, branch.get_str("then").unwrap().to_owned())
+            })
+            .collect();
+        let expected = [
+            ("$promoted", "promoted"),
+            ("$discarted", "rejected"),
+            ("$approved", "approved"),
+        ]
+        .map(|(flag, status)| (flag.to_owned(), status.to_owned()));
+        assert_eq!(branches, expected);
+        assert_eq!(switch.get_str("default"), Ok("pending"));
+    }
+
+    /// Field paths the pipeline reads, like `$approved`
+    fn field_paths(value: &Bson, paths: &mut Vec<String>) {
+        match value {
+            Bson::String(s) if s.starts_with('$') && !s.starts_with("$$") => {
+                paths.push(s[1..].to_owned())
+            }
+            Bson::Array(values) => values.iter().for_each(|v| field_paths(v, paths)),
+            Bson::Document(doc) => doc.values().for_each(|v| field_paths(v, paths)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn legacy_fields_are_removed() {
+        let pipeline = legacy_status_pipeline();
+        let unset: Vec<&str> = pipeline[2]
+            .get_array("$unset")
+            .unwrap()
+            .iter()
+            .filter_map(Bson::as_str)
+            .collect();
+        let mut read = Vec::new();
+        for stage in &pipeline[..2] {
+            field_paths(&Bson::Document(stage.clone()), &mut read);
+        }
+        // Fields the current schema still has
+        let kept = ["status", "discord_submitter_id", "time_of_submission"];
+        for field in read.iter().filter(|field| !kept.contains(&field.as_str())) {
+            assert!(unset.contains(&field.as_str()), "{} is left behind", field);
+        }
+    }
+}